@@ -0,0 +1,183 @@
+//! Anchors and references, for sharing a subtree between many nodes.
+//!
+//! Any node with an `id="name"` property is an anchor. A node refers to one
+//! with a `ref="name"` property or a `(ref)"name"` argument, and gets
+//! everything in the anchor except its `id`, with its own properties and
+//! children merged on top like a [layer](crate::merge). A property like
+//! `loot=(ref)"name"` becomes a `loot` child holding the anchor's contents.
+//!
+//! ```kdl
+//! loot id="common-loot" { - item="coin"; - item="rag" }
+//! goblin { loot ref="common-loot" }
+//! rat loot=(ref)"common-loot"
+//! ```
+//!
+//! Ids only have to be unique if something refers to them.
+//!
+//! References aren't followed while deserializing, since a node on its own
+//! can't see the rest of the document, so run [`resolve`] on the document
+//! first. Otherwise `ref` is just another property.
+
+use std::collections::HashMap;
+
+use kdl::{KdlDocument, KdlEntry, KdlNode};
+use thiserror::Error;
+
+use crate::inherit;
+
+/// A reference that can't be resolved.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AnchorError {
+  #[error("nothing has the id `{0}`")]
+  Dangling(String),
+  #[error("more than one node has the id `{0}`")]
+  Ambiguous(String),
+  #[error("`{0}` refers to something other than a string id")]
+  BadRef(String),
+  #[error("anchors refer to each other in a loop: {}", .0.join(" -> "))]
+  Cycle(Vec<String>),
+}
+
+/// Replace every reference in the document with what it refers to.
+///
+/// Call this before deserializing anything from a document that uses
+/// references.
+pub fn resolve(doc: &KdlDocument) -> Result<KdlDocument, AnchorError> {
+  let mut anchors = HashMap::<_, Vec<_>>::new();
+  index(doc.nodes(), &mut anchors);
+  let mut resolver = Resolver {
+    anchors,
+    done: HashMap::new(),
+  };
+
+  let mut out = doc.clone();
+  let mut changed = false;
+  for node in out.nodes_mut() {
+    if let Some(resolved) = resolver.node(node, &mut Vec::new())? {
+      *node = resolved;
+      changed = true;
+    }
+  }
+  if changed {
+    out.autoformat();
+  }
+  Ok(out)
+}
+
+fn index<'a>(
+  nodes: &'a [KdlNode],
+  anchors: &mut HashMap<&'a str, Vec<&'a KdlNode>>,
+) {
+  for node in nodes {
+    if let Some(id) = node.get("id").and_then(|it| it.as_string()) {
+      anchors.entry(id).or_default().push(node);
+    }
+    if let Some(kids) = node.children() {
+      index(kids.nodes(), anchors);
+    }
+  }
+}
+
+fn is_prop(entry: &KdlEntry, key: &str) -> bool {
+  entry.name().map(|it| it.value()) == Some(key)
+}
+
+fn is_ref(entry: &KdlEntry) -> bool {
+  entry.ty().map(|it| it.value()) == Some("ref")
+}
+
+struct Resolver<'a> {
+  anchors: HashMap<&'a str, Vec<&'a KdlNode>>,
+  /// Anchors that have been resolved already, without their `id`s
+  done: HashMap<&'a str, KdlNode>,
+}
+
+impl<'a> Resolver<'a> {
+  /// The node with its references filled in, or `None` if it has none.
+  fn node(
+    &mut self,
+    node: &KdlNode,
+    stack: &mut Vec<String>,
+  ) -> Result<Option<KdlNode>, AnchorError> {
+    let mut out = node.clone();
+    let mut changed = false;
+
+    // The node itself referring to an anchor
+    let target = node.entries().iter().position(|it| {
+      is_prop(it, "ref") || (it.name().is_none() && is_ref(it))
+    });
+    let base = match target {
+      Some(idx) => {
+        let entry = out.entries_mut().remove(idx);
+        Some(self.anchor(&entry, stack)?)
+      }
+      None => None,
+    };
+
+    // Properties referring to anchors become children
+    let (refs, entries): (Vec<_>, Vec<_>) = out
+      .entries()
+      .iter()
+      .cloned()
+      .partition(|it| it.name().is_some() && is_ref(it));
+    if !refs.is_empty() {
+      *out.entries_mut() = entries;
+      for entry in refs {
+        let mut kid = self.anchor(&entry, stack)?;
+        kid.set_name(entry.name().unwrap().clone());
+        out.ensure_children().nodes_mut().push(kid);
+      }
+      changed = true;
+    }
+
+    if let Some(children) = out.children_mut() {
+      for kid in children.nodes_mut() {
+        if let Some(resolved) = self.node(kid, stack)? {
+          *kid = resolved;
+          changed = true;
+        }
+      }
+    }
+
+    Ok(match base {
+      Some(base) => Some(inherit::merge(&base, &out)),
+      None => changed.then_some(out),
+    })
+  }
+
+  /// What a reference refers to, resolved, without its `id`
+  fn anchor(
+    &mut self,
+    entry: &KdlEntry,
+    stack: &mut Vec<String>,
+  ) -> Result<KdlNode, AnchorError> {
+    let name = entry.name().map_or("ref", |it| it.value());
+    let id = entry
+      .value()
+      .as_string()
+      .ok_or_else(|| AnchorError::BadRef(name.to_owned()))?;
+    let (id, anchor) = match self.anchors.get_key_value(id) {
+      Some((id, anchors)) => match anchors[..] {
+        [anchor] => (*id, anchor),
+        _ => return Err(AnchorError::Ambiguous(id.to_string())),
+      },
+      None => return Err(AnchorError::Dangling(id.to_owned())),
+    };
+    if let Some(done) = self.done.get(id) {
+      return Ok(done.clone());
+    }
+    if let Some(start) = stack.iter().position(|it| it == id) {
+      let mut cycle = stack[start..].to_vec();
+      cycle.push(id.to_owned());
+      return Err(AnchorError::Cycle(cycle));
+    }
+
+    stack.push(id.to_owned());
+    let mut resolved =
+      self.node(anchor, stack)?.unwrap_or_else(|| anchor.clone());
+    stack.pop();
+    resolved.entries_mut().retain(|it| !is_prop(it, "id"));
+    self.done.insert(id, resolved.clone());
+    Ok(resolved)
+  }
+}
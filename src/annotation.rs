@@ -0,0 +1,525 @@
+//! Type annotations the KDL spec reserves, like `(u8)` and `(ipv4)`.
+//!
+//! Values annotated with one of these are checked against it before they
+//! get deserialized, no matter what type they're being deserialized into.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use kdl::KdlValue;
+
+use crate::DeError;
+
+/// Check a value against its annotation, if that annotation is one the spec
+/// reserves. Other annotations are let through.
+pub(crate) fn check_reserved(
+  annotation: &str,
+  value: &KdlValue,
+) -> Result<(), DeError> {
+  // Anything can be missing
+  if value.is_null() {
+    return Ok(());
+  }
+
+  let res = match annotation {
+    _ if int_bounds(annotation).is_some() => check_int(annotation, value),
+    "f32" => match number(value).or_else(|| string_float("f32", value)) {
+      Some(f) if f.is_finite() && f.abs() > f32::MAX as f64 => {
+        Err("out of range for f32")
+      }
+      Some(_) => Ok(()),
+      None => Err("expected a number"),
+    },
+    "f64" => number(value)
+      .or_else(|| string_float("f64", value))
+      .map(|_| ())
+      .ok_or("expected a number"),
+    "decimal" => match value {
+      KdlValue::String(s) => check_decimal(s),
+      _ => number(value).map(|_| ()).ok_or("expected a number"),
+    },
+
+    "date-time" => string(value).and_then(check_date_time),
+    "date" => string(value).and_then(check_date),
+    "time" => string(value).and_then(check_time),
+    "duration" => string(value).and_then(check_duration),
+    "currency" => string(value).and_then(|s| check_letters(s, 3)),
+    "country-2" => string(value).and_then(|s| check_letters(s, 2)),
+    "country-3" => string(value).and_then(|s| check_letters(s, 3)),
+    "country-subdivision" => {
+      string(value).and_then(check_country_subdivision)
+    }
+    "email" | "idn-email" => string(value).and_then(check_email),
+    "hostname" => string(value).and_then(|s| check_hostname(s, false)),
+    "idn-hostname" => string(value).and_then(|s| check_hostname(s, true)),
+    "ipv4" => string(value).and_then(|s| {
+      s.parse::<Ipv4Addr>()
+        .map(|_| ())
+        .map_err(|_| "not an IPv4 address")
+    }),
+    "ipv6" => string(value).and_then(|s| {
+      s.parse::<Ipv6Addr>()
+        .map(|_| ())
+        .map_err(|_| "not an IPv6 address")
+    }),
+    "url" | "irl" => string(value).and_then(check_url),
+    "url-reference" | "irl-reference" | "url-template" => {
+      string(value).map(|_| ())
+    }
+    "uuid" => string(value).and_then(check_uuid),
+    "regex" => string(value).and_then(check_regex),
+    "base64" => string(value).and_then(|s| {
+      base64::decode(s)
+        .map(|_| ())
+        .map_err(|_| "not valid base64")
+    }),
+
+    _ => Ok(()),
+  };
+
+  res.map_err(|reason| DeError::AnnotationMismatch {
+    annotation: annotation.to_owned(),
+    value: value.to_string(),
+    reason,
+  })
+}
+
+/// Whether the spec reserves this annotation, or it names a byte encoding.
+/// These are type hints, unlike annotations that pick enum variants.
+pub(crate) fn is_reserved(annotation: &str) -> bool {
+  int_bounds(annotation).is_some()
+    || matches!(
+      annotation,
+      "f32"
+        | "f64"
+        | "decimal"
+        | "date-time"
+        | "date"
+        | "time"
+        | "duration"
+        | "currency"
+        | "country-2"
+        | "country-3"
+        | "country-subdivision"
+        | "email"
+        | "idn-email"
+        | "hostname"
+        | "idn-hostname"
+        | "ipv4"
+        | "ipv6"
+        | "url"
+        | "irl"
+        | "url-reference"
+        | "irl-reference"
+        | "url-template"
+        | "uuid"
+        | "regex"
+        | "base64"
+        | "base64url"
+        | "hex"
+        | "base32"
+        | "base85"
+    )
+}
+
+/// An integer that was written as a string, because it's too big for KDL
+/// to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BigInt {
+  Negative(i128),
+  Positive(u128),
+}
+
+impl From<i128> for BigInt {
+  fn from(it: i128) -> Self {
+    match u128::try_from(it) {
+      Ok(it) => BigInt::Positive(it),
+      Err(_) => BigInt::Negative(it),
+    }
+  }
+}
+
+impl std::fmt::Display for BigInt {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      BigInt::Negative(it) => it.fmt(f),
+      BigInt::Positive(it) => it.fmt(f),
+    }
+  }
+}
+
+/// The smallest and largest values of the integer type with this name
+fn int_bounds(annotation: &str) -> Option<(i128, u128)> {
+  macro_rules! bounds {
+    ($($ty:ident)*) => {
+      match annotation {
+        $(stringify!($ty) => Some(($ty::MIN as i128, $ty::MAX as u128)),)*
+        _ => None,
+      }
+    };
+  }
+  bounds! { i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize }
+}
+
+fn check_int(annotation: &str, value: &KdlValue) -> Result<(), &'static str> {
+  let int = match value {
+    KdlValue::Integer(it) => BigInt::from(*it),
+    _ => match string_int(annotation, value) {
+      Some(res) => return res.map(|_| ()),
+      None => return Err("expected an integer"),
+    },
+  };
+  check_bounds(annotation, int)
+}
+
+fn check_bounds(annotation: &str, int: BigInt) -> Result<(), &'static str> {
+  let Some((min, max)) = int_bounds(annotation) else {
+    return Ok(());
+  };
+  let fits = match int {
+    BigInt::Negative(it) => it >= min,
+    BigInt::Positive(it) => it <= max,
+  };
+  if fits {
+    Ok(())
+  } else {
+    Err("out of range for the annotated type")
+  }
+}
+
+/// Integers too big for KDL to parse (past `i64` in v1, or `i128` in v2) can
+/// be written as strings annotated with their type, like
+/// `(u64)"18446744073709551615"`.
+///
+/// Returns `None` if the value isn't a string or the annotation isn't
+/// an integer type.
+pub(crate) fn string_int(
+  annotation: &str,
+  value: &KdlValue,
+) -> Option<Result<BigInt, &'static str>> {
+  match value {
+    KdlValue::String(s) => parse_string_int(annotation, s),
+    _ => None,
+  }
+}
+
+/// Like [`string_int`], for when the string has already been pulled out.
+pub(crate) fn parse_string_int(
+  annotation: &str,
+  s: &str,
+) -> Option<Result<BigInt, &'static str>> {
+  int_bounds(annotation)?;
+  let res = parse_int(s)
+    .ok_or("not an integer")
+    .and_then(|int| check_bounds(annotation, int).map(|()| int));
+  Some(res)
+}
+
+/// KDL v1 has no literals for infinity or NaN, so they're written as strings
+/// annotated with a float type: `(f64)"inf"`, `(f64)"-inf"` and `(f64)"nan"`.
+/// (v2's `#inf`, `#-inf` and `#nan` are plain floats and don't need this.)
+///
+/// Returns `None` if the annotation isn't a float type or the value isn't
+/// one of those strings.
+pub(crate) fn string_float(
+  annotation: &str,
+  value: &KdlValue,
+) -> Option<f64> {
+  if !matches!(annotation, "f32" | "f64") {
+    return None;
+  }
+  match value {
+    KdlValue::String(s) => match s.as_str() {
+      "inf" | "+inf" => Some(f64::INFINITY),
+      "-inf" => Some(f64::NEG_INFINITY),
+      "nan" => Some(f64::NAN),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+/// Parse an integer the way KDL writes them, with an optional sign,
+/// radix prefix, and underscores
+fn parse_int(s: &str) -> Option<BigInt> {
+  let (negative, s) = match s.strip_prefix('-') {
+    Some(s) => (true, s),
+    None => (false, s.strip_prefix('+').unwrap_or(s)),
+  };
+  let (radix, s) = match s.get(..2) {
+    Some("0x") => (16, &s[2..]),
+    Some("0o") => (8, &s[2..]),
+    Some("0b") => (2, &s[2..]),
+    _ => (10, s),
+  };
+  if s.starts_with('_') {
+    return None;
+  }
+  let digits = s.replace('_', "");
+  // from_str_radix would let another sign through
+  if digits.starts_with(['+', '-']) {
+    return None;
+  }
+  let magnitude = u128::from_str_radix(&digits, radix).ok()?;
+  if negative {
+    0i128.checked_sub_unsigned(magnitude).map(BigInt::Negative)
+  } else {
+    Some(BigInt::Positive(magnitude))
+  }
+}
+
+fn number(value: &KdlValue) -> Option<f64> {
+  match value {
+    KdlValue::Integer(it) => Some(*it as f64),
+    KdlValue::Float(f) => Some(*f),
+    _ => None,
+  }
+}
+
+fn string(value: &KdlValue) -> Result<&str, &'static str> {
+  match value {
+    KdlValue::String(s) => Ok(s),
+    _ => Err("expected a string"),
+  }
+}
+
+/// Eat a run of ASCII digits off the front, returning them and the rest
+fn digits(s: &str) -> (&str, &str) {
+  let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  s.split_at(end)
+}
+
+/// Eat exactly `n` ASCII digits off the front and parse them
+fn fixed_digits(s: &str, n: usize) -> Option<(u32, &str)> {
+  let (ds, _) = digits(s);
+  if ds.len() < n {
+    return None;
+  }
+  let (ds, rest) = s.split_at(n);
+  ds.parse().ok().map(|it| (it, rest))
+}
+
+fn check_decimal(s: &str) -> Result<(), &'static str> {
+  let err = Err("not a decimal number");
+  let s = s.strip_prefix(['-', '+']).unwrap_or(s);
+  let (int, rest) = digits(s);
+  if int.is_empty() {
+    return err;
+  }
+  let rest = match rest.strip_prefix('.') {
+    Some(frac) => {
+      let (frac, rest) = digits(frac);
+      if frac.is_empty() {
+        return err;
+      }
+      rest
+    }
+    None => rest,
+  };
+  let rest = match rest.strip_prefix(['e', 'E']) {
+    Some(exp) => {
+      let exp = exp.strip_prefix(['-', '+']).unwrap_or(exp);
+      let (exp, rest) = digits(exp);
+      if exp.is_empty() {
+        return err;
+      }
+      rest
+    }
+    None => rest,
+  };
+  if rest.is_empty() {
+    Ok(())
+  } else {
+    err
+  }
+}
+
+/// `YYYY-MM-DD`, returning whatever is after it
+fn eat_date(s: &str) -> Option<&str> {
+  let (year, s) = fixed_digits(s, 4)?;
+  let (month, s) = fixed_digits(s.strip_prefix('-')?, 2)?;
+  let (day, s) = fixed_digits(s.strip_prefix('-')?, 2)?;
+
+  let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+  let month_len = match month {
+    1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+    4 | 6 | 9 | 11 => 30,
+    2 if leap => 29,
+    2 => 28,
+    _ => return None,
+  };
+  (1..=month_len).contains(&day).then_some(s)
+}
+
+/// `HH:MM:SS` with optional fractional seconds, returning whatever is
+/// after it
+fn eat_time(s: &str) -> Option<&str> {
+  let (hour, s) = fixed_digits(s, 2)?;
+  let (minute, s) = fixed_digits(s.strip_prefix(':')?, 2)?;
+  let (second, s) = fixed_digits(s.strip_prefix(':')?, 2)?;
+  // Leap seconds are a thing
+  if hour > 23 || minute > 59 || second > 60 {
+    return None;
+  }
+  match s.strip_prefix('.') {
+    Some(frac) => {
+      let (frac, s) = digits(frac);
+      (!frac.is_empty()).then_some(s)
+    }
+    None => Some(s),
+  }
+}
+
+fn check_date(s: &str) -> Result<(), &'static str> {
+  match eat_date(s) {
+    Some("") => Ok(()),
+    _ => Err("not an ISO 8601 date (YYYY-MM-DD)"),
+  }
+}
+
+fn check_time(s: &str) -> Result<(), &'static str> {
+  match eat_time(s) {
+    Some("") => Ok(()),
+    _ => Err("not an ISO 8601 time (HH:MM:SS)"),
+  }
+}
+
+fn check_date_time(s: &str) -> Result<(), &'static str> {
+  let ok = (|| {
+    let s = eat_date(s)?;
+    let s = eat_time(s.strip_prefix(['T', 't'])?)?;
+    match s {
+      "Z" | "z" => Some(()),
+      _ => {
+        let s = s.strip_prefix(['+', '-'])?;
+        let (hour, s) = fixed_digits(s, 2)?;
+        let (minute, s) = fixed_digits(s.strip_prefix(':')?, 2)?;
+        (hour <= 23 && minute <= 59 && s.is_empty()).then_some(())
+      }
+    }
+  })();
+  ok.ok_or("not an RFC 3339 date-time")
+}
+
+fn check_duration(s: &str) -> Result<(), &'static str> {
+  let err = Err("not an ISO 8601 duration");
+  let Some(s) = s.strip_prefix('P') else {
+    return err;
+  };
+  let (date, time) = match s.split_once('T') {
+    Some((date, time)) if !time.is_empty() => (date, Some(time)),
+    Some(_) => return err,
+    None => (s, None),
+  };
+
+  // Each part is some number followed by a designator, in order
+  fn eat_parts(mut s: &str, designators: &str) -> Option<usize> {
+    let mut count = 0;
+    let mut allowed = designators;
+    while !s.is_empty() {
+      let (int, rest) = digits(s);
+      let rest = match rest.strip_prefix(['.', ',']) {
+        Some(frac) => digits(frac).1,
+        None => rest,
+      };
+      let designator = rest.chars().next()?;
+      let idx = allowed.find(designator)?;
+      if int.is_empty() {
+        return None;
+      }
+      allowed = &allowed[idx + 1..];
+      s = &rest[1..];
+      count += 1;
+    }
+    Some(count)
+  }
+
+  match (eat_parts(date, "YMWD"), time.map(|t| eat_parts(t, "HMS"))) {
+    (Some(d), None) if d > 0 => Ok(()),
+    (Some(_), Some(Some(t))) if t > 0 => Ok(()),
+    _ => err,
+  }
+}
+
+fn check_letters(s: &str, len: usize) -> Result<(), &'static str> {
+  if s.len() == len && s.bytes().all(|b| b.is_ascii_uppercase()) {
+    Ok(())
+  } else if len == 2 {
+    Err("expected 2 uppercase letters")
+  } else {
+    Err("expected 3 uppercase letters")
+  }
+}
+
+fn check_country_subdivision(s: &str) -> Result<(), &'static str> {
+  let err = Err("not an ISO 3166-2 subdivision code (like US-CA)");
+  let Some((country, sub)) = s.split_once('-') else {
+    return err;
+  };
+  if check_letters(country, 2).is_ok()
+    && (1..=3).contains(&sub.len())
+    && sub
+      .bytes()
+      .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+  {
+    Ok(())
+  } else {
+    err
+  }
+}
+
+fn check_email(s: &str) -> Result<(), &'static str> {
+  match s.rsplit_once('@') {
+    Some((local, domain))
+      if !local.is_empty() && check_hostname(domain, true).is_ok() =>
+    {
+      Ok(())
+    }
+    _ => Err("not an email address"),
+  }
+}
+
+fn check_hostname(s: &str, idn: bool) -> Result<(), &'static str> {
+  let label_ok = |label: &str| {
+    !label.is_empty()
+      && label.len() <= 63
+      && !label.starts_with('-')
+      && !label.ends_with('-')
+      && label.chars().all(|c| {
+        c == '-' || c.is_ascii_alphanumeric() || (idn && c.is_alphanumeric())
+      })
+  };
+  if !s.is_empty() && s.len() <= 253 && s.split('.').all(label_ok) {
+    Ok(())
+  } else {
+    Err("not a hostname")
+  }
+}
+
+#[cfg(feature = "url")]
+fn check_url(s: &str) -> Result<(), &'static str> {
+  url::Url::parse(s).map(|_| ()).map_err(|_| "not a URL")
+}
+#[cfg(not(feature = "url"))]
+fn check_url(_: &str) -> Result<(), &'static str> {
+  Ok(())
+}
+
+#[cfg(feature = "uuid")]
+fn check_uuid(s: &str) -> Result<(), &'static str> {
+  uuid::Uuid::parse_str(s).map(|_| ()).map_err(|_| "not a UUID")
+}
+#[cfg(not(feature = "uuid"))]
+fn check_uuid(_: &str) -> Result<(), &'static str> {
+  Ok(())
+}
+
+#[cfg(feature = "regex")]
+fn check_regex(s: &str) -> Result<(), &'static str> {
+  regex::Regex::new(s)
+    .map(|_| ())
+    .map_err(|_| "not a valid regex")
+}
+#[cfg(not(feature = "regex"))]
+fn check_regex(_: &str) -> Result<(), &'static str> {
+  Ok(())
+}
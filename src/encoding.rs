@@ -0,0 +1,112 @@
+//! Text encodings for binary data, picked by annotating a string.
+
+use crate::DeError;
+
+/// Decode the string if the annotation is the name of an encoding we know.
+pub(crate) fn decode(
+  annotation: &str,
+  s: &str,
+) -> Option<Result<Vec<u8>, DeError>> {
+  let res = match annotation {
+    "base64" => base64::decode(s).map_err(DeError::from),
+    "base64url" => {
+      let config = if s.ends_with('=') {
+        base64::URL_SAFE
+      } else {
+        base64::URL_SAFE_NO_PAD
+      };
+      base64::decode_config(s, config).map_err(DeError::from)
+    }
+    "hex" => decode_hex(s).map_err(|reason| DeError::BytesDecode {
+      encoding: "hex",
+      reason,
+    }),
+    "base32" => decode_base32(s).map_err(|reason| DeError::BytesDecode {
+      encoding: "base32",
+      reason,
+    }),
+    "base85" => decode_base85(s).map_err(|reason| DeError::BytesDecode {
+      encoding: "base85",
+      reason,
+    }),
+    _ => return None,
+  };
+  Some(res)
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, String> {
+  if !s.len().is_multiple_of(2) {
+    return Err("odd number of digits".to_owned());
+  }
+  s.as_bytes()
+    .chunks(2)
+    .map(|pair| {
+      // this can't split a char in half because anything non-ascii
+      // is invalid hex anyways
+      std::str::from_utf8(pair)
+        .ok()
+        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        .ok_or_else(|| {
+          format!("invalid hex digits {:?}", String::from_utf8_lossy(pair))
+        })
+    })
+    .collect()
+}
+
+/// RFC 4648 base32. Padding is optional and case doesn't matter.
+fn decode_base32(s: &str) -> Result<Vec<u8>, String> {
+  let s = s.trim_end_matches('=');
+  let mut out = Vec::with_capacity(s.len() * 5 / 8);
+  let mut buf = 0u64;
+  let mut bits = 0;
+  for c in s.chars() {
+    let digit = match c.to_ascii_uppercase() {
+      c @ 'A'..='Z' => c as u64 - 'A' as u64,
+      c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+      oh_no => return Err(format!("invalid character {:?}", oh_no)),
+    };
+    buf = (buf << 5) | digit;
+    bits += 5;
+    if bits >= 8 {
+      bits -= 8;
+      out.push((buf >> bits) as u8);
+      buf &= (1 << bits) - 1;
+    }
+  }
+  Ok(out)
+}
+
+/// The RFC 1924 alphabet, also used by git and Python's `b85encode`
+const BASE85_ALPHABET: &[u8; 85] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+abcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+fn decode_base85(s: &str) -> Result<Vec<u8>, String> {
+  let digits = s
+    .bytes()
+    .map(|b| {
+      BASE85_ALPHABET
+        .iter()
+        .position(|it| *it == b)
+        .map(|it| it as u32)
+        .ok_or_else(|| format!("invalid character {:?}", b as char))
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let mut out = Vec::with_capacity(digits.len() * 4 / 5);
+  for chunk in digits.chunks(5) {
+    if chunk.len() == 1 {
+      return Err("a trailing group must be at least 2 characters".to_owned());
+    }
+    // Short groups are padded with the highest digit
+    let acc = (0..5).try_fold(0u32, |acc, i| {
+      acc
+        .checked_mul(85)?
+        .checked_add(chunk.get(i).copied().unwrap_or(84))
+    });
+    let Some(acc) = acc else {
+      return Err("group is out of range".to_owned());
+    };
+    out.extend_from_slice(&acc.to_be_bytes()[..chunk.len() - 1]);
+  }
+  Ok(out)
+}
@@ -0,0 +1,282 @@
+//! Example documents for Rust types, found by tracing their `Deserialize`
+//! impls.
+
+use heck::ToKebabCase;
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlNodeFormat, KdlValue};
+use serde::de::DeserializeOwned;
+
+use crate::{
+  reflect::{self, Container, Literal, Registry, Shape},
+  DeError,
+};
+
+/// Write an example document with one node named `name`, which
+/// deserializes into a `T`.
+///
+/// Every field shows up with a placeholder value: fields that can be a
+/// single value as properties, and everything else as children. Comments
+/// point out optional fields, lists, and the other alternatives of enums.
+pub fn example<T: DeserializeOwned>(
+  name: &str,
+) -> Result<KdlDocument, DeError> {
+  let (shape, registry) = reflect::trace::<T>()?;
+  let mut gen = Example {
+    registry: &registry,
+    stack: Vec::new(),
+  };
+
+  let mut root = KdlNode::new(name);
+  let mut notes = Vec::new();
+  gen.body(&shape, &mut root, &mut notes);
+  comment(&mut root, &notes);
+
+  let mut doc = KdlDocument::new();
+  doc.nodes_mut().push(root);
+  doc.autoformat();
+  Ok(doc)
+}
+
+/// Put `// note` lines above the node
+fn comment(node: &mut KdlNode, notes: &[String]) {
+  if notes.is_empty() {
+    return;
+  }
+  let leading = notes.iter().map(|it| format!("// {}\n", it)).collect();
+  node.set_format(KdlNodeFormat {
+    leading,
+    ..Default::default()
+  });
+}
+
+fn prop(key: &str, mut entry: KdlEntry) -> KdlEntry {
+  entry.set_name(Some(key));
+  entry
+}
+
+fn push(node: &mut KdlNode, kid: KdlNode) {
+  node.ensure_children().nodes_mut().push(kid);
+}
+
+/// A placeholder value, and the alternatives it stands for if it's an enum
+type Placeholder = (KdlEntry, Option<String>);
+
+fn placeholder(literal: &Literal) -> Option<Placeholder> {
+  let shape = match literal {
+    Literal::Scalar(shape) => shape,
+    Literal::Enum(variants) => return enum_placeholder(variants),
+  };
+  let value: KdlValue = match shape {
+    Shape::Unit => KdlValue::Null,
+    Shape::Bool => false.into(),
+    Shape::F32 | Shape::F64 => 0.0.into(),
+    Shape::Char => "x".into(),
+    Shape::Str => "...".into(),
+    // The integers
+    _ => 0.into(),
+  };
+  Some((KdlEntry::new(value), None))
+}
+
+fn enum_placeholder(
+  variants: &[(&'static str, Option<Literal>)],
+) -> Option<Placeholder> {
+  let mut alternatives = Vec::new();
+  for (name, inner) in variants {
+    let entry = match inner {
+      None => KdlEntry::new(*name),
+      Some(inner) => {
+        let (mut entry, _) = placeholder(inner)?;
+        entry.set_ty(*name);
+        entry
+      }
+    };
+    alternatives.push(entry);
+  }
+  let first = alternatives.first()?.clone();
+  let note = (alternatives.len() > 1).then(|| {
+    let all: Vec<_> = alternatives
+      .iter()
+      .map(|it| it.to_string().trim().to_owned())
+      .collect();
+    all.join(", ")
+  });
+  Some((first, note))
+}
+
+struct Example<'r> {
+  registry: &'r Registry,
+  /// Containers we're inside of
+  stack: Vec<&'static str>,
+}
+
+impl<'r> Example<'r> {
+  /// A placeholder for a shape that's written as a single value, or `None`
+  /// if it isn't one.
+  fn literal(&self, shape: &Shape) -> Option<Placeholder> {
+    placeholder(&reflect::literal(shape, self.registry)?)
+  }
+
+  /// Fill in a node holding `shape`, writing down anything worth saying
+  /// about it.
+  fn body(
+    &mut self,
+    shape: &Shape,
+    node: &mut KdlNode,
+    notes: &mut Vec<String>,
+  ) {
+    if let Some((entry, note)) = self.literal(shape) {
+      node.push(entry);
+      if let Some(note) = note {
+        notes.push(format!("one of {}", note));
+      }
+      return;
+    }
+
+    match shape {
+      Shape::Option(inner) => self.body(inner, node, notes),
+      Shape::Seq(inner) => self.seq(inner, node, notes),
+      Shape::Tuple(items) => self.tuple(items, node, notes),
+      Shape::Bytes => {
+        node.push(0);
+        notes.push("any number of byte arguments".to_owned());
+      }
+      Shape::Map(key, value) => {
+        let key = match self.literal(key) {
+          Some((entry, _)) if !entry.value().is_string() => {
+            entry.value().to_string()
+          }
+          _ => "key".to_owned(),
+        };
+        if let Some((entry, note)) = self.literal(value) {
+          node.push(prop(key.as_str(), entry));
+          notes.push("any number of properties".to_owned());
+          if let Some(note) = note {
+            notes.push(format!("each one of {}", note));
+          }
+        } else {
+          let mut kid = KdlNode::new(key.as_str());
+          let mut kid_notes = Vec::new();
+          self.body(value, &mut kid, &mut kid_notes);
+          comment(&mut kid, &kid_notes);
+          push(node, kid);
+          notes.push("any number of children".to_owned());
+        }
+      }
+      Shape::Named(name) => {
+        if self.stack.contains(name) {
+          notes.push(format!("another `{}`, left out so this ends", name));
+        } else {
+          self.stack.push(name);
+          self.named(name, node, notes);
+          self.stack.pop();
+        }
+      }
+      _ => notes.push("anything goes here".to_owned()),
+    }
+  }
+
+  fn named(
+    &mut self,
+    name: &str,
+    node: &mut KdlNode,
+    notes: &mut Vec<String>,
+  ) {
+    let Some(container) = self.registry.get(name) else {
+      return;
+    };
+    match container {
+      Container::Unit => {}
+      Container::Newtype(inner) => self.body(inner, node, notes),
+      Container::Tuple(items) => self.tuple(items, node, notes),
+      Container::Struct(fields) => {
+        for (field, shape) in fields {
+          let key = field.to_kebab_case();
+          let optional = matches!(shape, Shape::Option(_));
+          if let Some((entry, note)) = self.literal(shape) {
+            node.push(prop(key.as_str(), entry));
+            if optional {
+              notes.push(format!("`{}` is optional", key));
+            }
+            if let Some(note) = note {
+              notes.push(format!("`{}` is one of {}", key, note));
+            }
+          } else {
+            let mut kid = KdlNode::new(key.as_str());
+            let mut kid_notes = Vec::new();
+            if optional {
+              kid_notes.push("optional".to_owned());
+            }
+            self.body(shape, &mut kid, &mut kid_notes);
+            comment(&mut kid, &kid_notes);
+            push(node, kid);
+          }
+        }
+      }
+      Container::Enum(_) => notes.push(format!(
+        "`{}` can't be written in KDL, since knurdy only reads enums \
+         written as one value",
+        name
+      )),
+    }
+  }
+
+  /// One argument if the items are literals, and one `-` child if not
+  fn seq(
+    &mut self,
+    item: &Shape,
+    node: &mut KdlNode,
+    notes: &mut Vec<String>,
+  ) {
+    if let Some((entry, note)) = self.literal(item) {
+      node.push(entry);
+      notes.push("any number of arguments".to_owned());
+      if let Some(note) = note {
+        notes.push(format!("each one of {}", note));
+      }
+      return;
+    }
+
+    if let Shape::Named(name) = item {
+      if self.stack.contains(name) {
+        // An empty list still needs braces
+        node.ensure_children();
+        notes.push(format!("any number of `-` children, each a `{}`", name));
+        return;
+      }
+    }
+    let mut kid = KdlNode::new("-");
+    let mut kid_notes = Vec::new();
+    self.body(item, &mut kid, &mut kid_notes);
+    comment(&mut kid, &kid_notes);
+    push(node, kid);
+    notes.push("any number of `-` children".to_owned());
+  }
+
+  fn tuple(
+    &mut self,
+    items: &[Shape],
+    node: &mut KdlNode,
+    notes: &mut Vec<String>,
+  ) {
+    let literals: Option<Vec<_>> =
+      items.iter().map(|it| self.literal(it)).collect();
+    if let Some(literals) = literals {
+      for (entry, note) in literals {
+        if let Some(note) = note {
+          let index = node.entries().len();
+          notes.push(format!("argument {} is one of {}", index, note));
+        }
+        node.push(entry);
+      }
+      return;
+    }
+
+    for item in items {
+      let mut kid = KdlNode::new("-");
+      let mut kid_notes = Vec::new();
+      self.body(item, &mut kid, &mut kid_notes);
+      comment(&mut kid, &kid_notes);
+      push(node, kid);
+    }
+  }
+}
@@ -0,0 +1,193 @@
+//! `include "path.kdl"` directives, for documents split over many files.
+//!
+//! Top-level `include` nodes are replaced by the top-level nodes of the
+//! files they name, in order, and those files can include more in turn.
+//! Relative paths are relative to the directory of the including file.
+//!
+//! Files are read through a [`Loader`], so they can come from anywhere;
+//! [`FileLoader`] reads from disk and [`MemoryLoader`] from a map.
+
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::{Component, Path, PathBuf},
+};
+
+use kdl::{KdlDocument, KdlError, KdlNode};
+use thiserror::Error;
+
+/// Something wrong with an include, and the file it's in.
+#[derive(Error, Debug)]
+pub enum IncludeError {
+  #[error("{file}: couldn't load `{path}`: {source}")]
+  Load {
+    file: PathBuf,
+    path: PathBuf,
+    source: io::Error,
+  },
+  #[error("{file}: {source}")]
+  Parse { file: PathBuf, source: KdlError },
+  #[error("{file}: `include` takes paths as string arguments, and no props")]
+  BadInclude { file: PathBuf },
+  #[error("{file}: files include each other in a loop: {}", show(.chain))]
+  Cycle { file: PathBuf, chain: Vec<PathBuf> },
+}
+
+fn show(chain: &[PathBuf]) -> String {
+  let names: Vec<_> = chain.iter().map(|it| it.display().to_string()).collect();
+  names.join(" -> ")
+}
+
+/// Somewhere to read included files from.
+pub trait Loader {
+  /// Read the text of the file at `path`.
+  fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Reads files from disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileLoader;
+
+impl Loader for FileLoader {
+  fn load(&self, path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+  }
+}
+
+/// Reads files from memory, which is handy for tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+  files: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add a file at `path` holding `text`.
+  pub fn with_file(
+    mut self,
+    path: impl AsRef<Path>,
+    text: impl Into<String>,
+  ) -> Self {
+    self.files.insert(normalize(path.as_ref()), text.into());
+    self
+  }
+}
+
+impl Loader for MemoryLoader {
+  fn load(&self, path: &Path) -> io::Result<String> {
+    self.files.get(&normalize(path)).cloned().ok_or_else(|| {
+      io::Error::new(io::ErrorKind::NotFound, "no such file in memory")
+    })
+  }
+}
+
+/// Load the file at `path` and everything it includes.
+pub fn load(
+  path: impl AsRef<Path>,
+  loader: &impl Loader,
+) -> Result<KdlDocument, IncludeError> {
+  let path = normalize(path.as_ref());
+  let doc = read(&path, &path, loader)?;
+  expand(&doc, path, loader)
+}
+
+/// Splice in everything a document includes, as if it was read from the
+/// file at `path`.
+pub fn expand(
+  doc: &KdlDocument,
+  path: impl AsRef<Path>,
+  loader: &impl Loader,
+) -> Result<KdlDocument, IncludeError> {
+  let path = normalize(path.as_ref());
+  let mut chain = vec![path.clone()];
+  let nodes = splice(doc, &path, loader, &mut chain)?;
+  let mut out = doc.clone();
+  *out.nodes_mut() = nodes;
+  Ok(out)
+}
+
+fn read(
+  file: &Path,
+  path: &Path,
+  loader: &impl Loader,
+) -> Result<KdlDocument, IncludeError> {
+  let text = loader.load(path).map_err(|source| IncludeError::Load {
+    file: file.to_owned(),
+    path: path.to_owned(),
+    source,
+  })?;
+  text.parse().map_err(|source| IncludeError::Parse {
+    file: path.to_owned(),
+    source,
+  })
+}
+
+fn splice(
+  doc: &KdlDocument,
+  file: &Path,
+  loader: &impl Loader,
+  chain: &mut Vec<PathBuf>,
+) -> Result<Vec<KdlNode>, IncludeError> {
+  let mut nodes = Vec::new();
+  for node in doc.nodes() {
+    if node.name().value() != "include" {
+      nodes.push(node.clone());
+      continue;
+    }
+
+    for entry in node.entries() {
+      let bad = || IncludeError::BadInclude {
+        file: file.to_owned(),
+      };
+      if entry.name().is_some() {
+        return Err(bad());
+      }
+      let target = entry.value().as_string().ok_or_else(bad)?;
+      let dir = file.parent().unwrap_or(Path::new(""));
+      let target = normalize(&dir.join(target));
+      if let Some(start) = chain.iter().position(|it| *it == target) {
+        let mut cycle = chain[start..].to_vec();
+        cycle.push(target);
+        return Err(IncludeError::Cycle {
+          file: file.to_owned(),
+          chain: cycle,
+        });
+      }
+
+      let included = read(file, &target, loader)?;
+      chain.push(target.clone());
+      for mut node in splice(&included, &target, loader, chain)? {
+        // The last node in a file might not have a newline after it
+        if let Some(format) = node.format_mut() {
+          if format.terminator.is_empty() {
+            format.terminator = "\n".into();
+          }
+        }
+        nodes.push(node);
+      }
+      chain.pop();
+    }
+  }
+  Ok(nodes)
+}
+
+/// Clean up `.` and `..` without touching the filesystem, so the same file
+/// always gets the same path.
+fn normalize(path: &Path) -> PathBuf {
+  let mut out = PathBuf::new();
+  for part in path.components() {
+    match part {
+      Component::CurDir => {}
+      Component::ParentDir
+        if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+      {
+        out.pop();
+      }
+      other => out.push(other),
+    }
+  }
+  out
+}
@@ -0,0 +1,150 @@
+//! Blueprint inheritance, resolved on the document before deserializing.
+//!
+//! A top-level node can name other top-level nodes as its parents, with an
+//! `extends="parent"` property or an `inherits "parent" ...` child. Parents
+//! are resolved first and merged in order, then the node is merged on top,
+//! the same way as [layers](crate::merge) are. So properties and children
+//! override the parent's, arguments and `-` children replace the parent's
+//! unless marked `(append)`, and `(override)` and `(remove)` work too.
+//!
+//! The `extends` property and `inherits` child are left out of the result.
+
+use std::collections::HashMap;
+
+use kdl::{KdlDocument, KdlEntry, KdlNode};
+use thiserror::Error;
+
+use crate::merge::{merge_into, Origin, SeqPolicy};
+
+/// Something that stopped a document's inheritance from being resolved.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InheritError {
+  #[error("`{node}` extends `{parent}`, which doesn't exist")]
+  UnknownParent { node: String, parent: String },
+  #[error("`{node}` extends `{parent}`, but there's more than one of those")]
+  AmbiguousParent { node: String, parent: String },
+  #[error("`{0}` names a parent with something other than a string")]
+  BadParent(String),
+  #[error("blueprints inherit from each other in a loop: {}", .0.join(" -> "))]
+  Cycle(Vec<String>),
+}
+
+/// Resolve every top-level node's parents, returning a document where each
+/// node has everything it inherits merged in.
+///
+/// Nodes that don't inherit anything are copied as they are.
+pub fn resolve(doc: &KdlDocument) -> Result<KdlDocument, InheritError> {
+  let mut by_name = HashMap::<_, Vec<_>>::new();
+  for (idx, node) in doc.nodes().iter().enumerate() {
+    by_name.entry(node.name().value()).or_default().push(idx);
+  }
+  let mut resolver = Resolver {
+    nodes: doc.nodes(),
+    by_name,
+    done: HashMap::new(),
+    chain: Vec::new(),
+  };
+
+  let mut out = doc.clone();
+  for idx in 0..doc.nodes().len() {
+    out.nodes_mut()[idx] = resolver.resolve(idx)?;
+  }
+  Ok(out)
+}
+
+/// Merge `child` on top of `parent`, the same way as a layer in
+/// [`merge`](crate::merge).
+pub fn merge(parent: &KdlNode, child: &KdlNode) -> KdlNode {
+  let mut out = parent.clone();
+  let mut origin = Origin::of(parent, 0);
+  merge_into(&mut out, &mut origin, child, 1, SeqPolicy::Replace);
+  out.autoformat();
+  out
+}
+
+struct Resolver<'a> {
+  nodes: &'a [KdlNode],
+  by_name: HashMap<&'a str, Vec<usize>>,
+  done: HashMap<usize, KdlNode>,
+  /// Names of the nodes being resolved, for finding loops
+  chain: Vec<String>,
+}
+
+impl<'a> Resolver<'a> {
+  fn resolve(&mut self, idx: usize) -> Result<KdlNode, InheritError> {
+    if let Some(done) = self.done.get(&idx) {
+      return Ok(done.clone());
+    }
+    let node = &self.nodes[idx];
+    let name = node.name().value();
+    let (parents, own) = split_parents(node)?;
+    if parents.is_empty() {
+      return Ok(node.clone());
+    }
+    if let Some(start) = self.chain.iter().position(|it| it == name) {
+      let mut cycle = self.chain[start..].to_vec();
+      cycle.push(name.to_owned());
+      return Err(InheritError::Cycle(cycle));
+    }
+
+    self.chain.push(name.to_owned());
+    let mut base: Option<KdlNode> = None;
+    for parent in parents {
+      let parent_idx = match self.by_name.get(parent.as_str()) {
+        Some(found) if found.len() == 1 => found[0],
+        found => {
+          let (node, parent) = (name.to_owned(), parent);
+          return Err(match found {
+            Some(_) => InheritError::AmbiguousParent { node, parent },
+            None => InheritError::UnknownParent { node, parent },
+          });
+        }
+      };
+      let parent = self.resolve(parent_idx)?;
+      base = Some(match base {
+        Some(base) => merge(&base, &parent),
+        None => parent,
+      });
+    }
+    self.chain.pop();
+
+    let resolved = merge(&base.unwrap(), &own);
+    self.done.insert(idx, resolved.clone());
+    Ok(resolved)
+  }
+}
+
+/// The names of a node's parents, and the node without the `extends` and
+/// `inherits` that named them.
+fn split_parents(
+  node: &KdlNode,
+) -> Result<(Vec<String>, KdlNode), InheritError> {
+  let bad = || InheritError::BadParent(node.name().value().to_owned());
+  let name_of = |entry: &KdlEntry| {
+    entry.value().as_string().map(str::to_owned).ok_or_else(bad)
+  };
+
+  let mut parents = Vec::new();
+  let mut own = node.clone();
+  own.entries_mut().retain(|it| {
+    it.name().map(|it| it.value()) != Some("extends")
+  });
+  for entry in node.entries() {
+    if entry.name().map(|it| it.value()) == Some("extends") {
+      parents.push(name_of(entry)?);
+    }
+  }
+  if let Some(kids) = own.children_mut() {
+    let inherits = |it: &KdlNode| it.name().value() == "inherits";
+    for kid in kids.nodes().iter().filter(|it| inherits(it)) {
+      for entry in kid.entries() {
+        if entry.name().is_some() {
+          return Err(bad());
+        }
+        parents.push(name_of(entry)?);
+      }
+    }
+    kids.nodes_mut().retain(|it| !inherits(it));
+  }
+  Ok((parents, own))
+}
@@ -0,0 +1,51 @@
+//! Replacing `${name}` in strings, when it's turned on in the options.
+
+use std::borrow::Cow;
+
+use crate::{DeError, DeOptions};
+
+/// Fill in the variables in `text`, if interpolation is on.
+pub(crate) fn interpolate<'a>(
+  text: &'a str,
+  options: &DeOptions,
+) -> Result<Cow<'a, str>, DeError> {
+  if !options.interpolation() {
+    return Ok(Cow::Borrowed(text));
+  }
+  fill(text, options)
+}
+
+/// Fill in the variables in `text`. `$$` is a literal `$`.
+pub(crate) fn fill<'a>(
+  text: &'a str,
+  options: &DeOptions,
+) -> Result<Cow<'a, str>, DeError> {
+  if !text.contains('$') {
+    return Ok(Cow::Borrowed(text));
+  }
+
+  let mut out = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(idx) = rest.find('$') {
+    out.push_str(&rest[..idx]);
+    rest = &rest[idx + 1..];
+    if let Some(after) = rest.strip_prefix('$') {
+      out.push('$');
+      rest = after;
+    } else if let Some(after) = rest.strip_prefix('{') {
+      let end = after
+        .find('}')
+        .ok_or_else(|| DeError::UnclosedInterpolation(text.to_owned()))?;
+      let name = &after[..end];
+      let value = options
+        .var(name)
+        .ok_or_else(|| DeError::UndefinedVariable(name.to_owned()))?;
+      out.push_str(&value);
+      rest = &after[end + 1..];
+    } else {
+      out.push('$');
+    }
+  }
+  out.push_str(rest);
+  Ok(Cow::Owned(out))
+}
@@ -0,0 +1,150 @@
+//! [JSON-in-KDL](https://github.com/kdl-org/kdl/blob/main/JSON-IN-KDL.md),
+//! the KDL project's way of writing arbitrary JSON as KDL.
+//!
+//! This is stricter than knurdy's usual mapping, so other tools that speak
+//! JiK can read what we write and the other way around:
+//!
+//! - A node with a single argument and nothing else is that value.
+//! - A node with several arguments, or children all named `-`, is an array.
+//!   Arguments come first, then children.
+//! - A node with properties or children with other names is an object.
+//!   Objects can't have arguments.
+//! - `(array)` and `(object)` on a node force it to be one, so `(array)-`
+//!   is `[]`, `(array)- 1` is `[1]` and `(object)-` is `{}`.
+//!
+//! Node names at the top level aren't used for anything; `-` is customary.
+
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{serialize, DeError, KdlAnnotatedValueDeser, SerError, Value};
+
+/// Deserialize a JiK node.
+pub fn deserialize_node<T: DeserializeOwned>(
+  node: &KdlNode,
+) -> Result<T, DeError> {
+  T::deserialize(node_value(node)?)
+}
+
+/// Serialize a value as a JiK node named `-`.
+pub fn to_node<T: Serialize + ?Sized>(
+  value: &T,
+) -> Result<KdlNode, SerError> {
+  Ok(value_node("-", serialize::to_value(value)?))
+}
+
+fn node_value(node: &KdlNode) -> Result<Value, DeError> {
+  let mut args = Vec::new();
+  let mut props = Vec::new();
+  for entry in node.entries() {
+    let value = Value::deserialize(KdlAnnotatedValueDeser::new(entry))?;
+    match entry.name() {
+      Some(name) => props.push((name.value().to_owned(), value)),
+      None => args.push(value),
+    }
+  }
+  let kids = node.children().map_or(&[][..], |kids| kids.nodes());
+
+  let array = match node.ty().map(|ty| ty.value()) {
+    Some("array") => true,
+    Some("object") => false,
+    _ => {
+      let dashes = kids.iter().all(|kid| kid.name().value() == "-");
+      if props.is_empty() && dashes {
+        match (args.len(), kids.len()) {
+          (0, 0) => {
+            return Err(DeError::InvalidJik(
+              "empty node without an `(array)` or `(object)` annotation",
+            ))
+          }
+          (1, 0) => return Ok(args.pop().unwrap()),
+          _ => true,
+        }
+      } else {
+        false
+      }
+    }
+  };
+
+  if array {
+    if !props.is_empty() {
+      return Err(DeError::InvalidJik("arrays can't have properties"));
+    }
+    for kid in kids {
+      args.push(node_value(kid)?);
+    }
+    Ok(Value::Seq(args))
+  } else {
+    if !args.is_empty() {
+      return Err(DeError::InvalidJik("objects can't have arguments"));
+    }
+    for kid in kids {
+      props.push((kid.name().value().to_owned(), node_value(kid)?));
+    }
+    Ok(Value::Map(props))
+  }
+}
+
+fn value_node(name: &str, value: Value) -> KdlNode {
+  let mut node = KdlNode::new(name);
+  match value {
+    Value::Seq(items) => {
+      if items.iter().all(|it| literal(it).is_some()) {
+        // These don't look like arrays on their own
+        if items.len() <= 1 {
+          node.set_ty("array");
+        }
+        node
+          .entries_mut()
+          .extend(items.iter().filter_map(literal));
+      } else {
+        let kids = items.into_iter().map(|it| value_node("-", it));
+        set_children(&mut node, kids);
+      }
+    }
+    // Bytes are arrays of numbers, like in serde_json
+    Value::Bytes(bytes) => {
+      let items = bytes.into_iter().map(|b| Value::Int(b.into())).collect();
+      return value_node(name, Value::Seq(items));
+    }
+    Value::Map(entries) => {
+      if entries.iter().all(|(key, _)| key == "-") {
+        node.set_ty("object");
+      }
+      let kids = entries.into_iter().map(|(key, it)| value_node(&key, it));
+      set_children(&mut node, kids);
+    }
+    Value::Annotated(_, inner) if literal(&inner).is_none() => {
+      return value_node(name, *inner);
+    }
+    // `Value::Node` never comes out of `to_value`
+    it => node.entries_mut().extend(literal(&it)),
+  }
+  node
+}
+
+fn set_children(node: &mut KdlNode, kids: impl Iterator<Item = KdlNode>) {
+  let mut doc = KdlDocument::new();
+  doc.nodes_mut().extend(kids);
+  if !doc.nodes().is_empty() {
+    node.set_children(doc);
+  }
+}
+
+/// The entry for a value that can be one
+fn literal(value: &Value) -> Option<KdlEntry> {
+  let value = match value {
+    Value::Unit => KdlValue::Null,
+    Value::Bool(b) => KdlValue::Bool(*b),
+    Value::Int(it) => KdlValue::Integer(*it),
+    Value::Float(f) => KdlValue::Float(*f),
+    Value::String(s) => KdlValue::String(s.clone()),
+    Value::Annotated(ann, inner) => {
+      let mut entry = literal(inner)?;
+      entry.set_ty(ann.as_str());
+      return Some(entry);
+    }
+    _ => return None,
+  };
+  Some(KdlEntry::new(value))
+}
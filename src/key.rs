@@ -0,0 +1,111 @@
+//! Map keys, which are always node or property names.
+
+use std::borrow::Cow;
+
+use serde::{
+  de::{self, Error, IntoDeserializer, Unexpected, Visitor},
+  forward_to_deserialize_any,
+};
+
+use crate::DeError;
+
+/// Deserializes a node or property name used as a map key, or XiK text.
+///
+/// Like serde_json's map keys, numbers, bools and chars are parsed out of
+/// the name, and unit enum variants are picked by it.
+pub(crate) struct KeyDeser<'de>(pub(crate) Cow<'de, str>);
+
+/// Parse the key with `FromStr`
+macro_rules! parse_key {
+  (@ $ty:ident) => {
+    paste::paste! {
+      fn [< deserialize_ $ty >]<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+      where
+        V: Visitor<'de>,
+      {
+        match self.0.parse::<$ty>() {
+          Ok(it) => visitor.[< visit_ $ty >](it),
+          Err(_) => Err(DeError::invalid_type(Unexpected::Str(&self.0), &visitor)),
+        }
+      }
+    }
+  };
+  ( $($ty:ident)* ) => {
+    $(
+      parse_key!(@ $ty);
+    )*
+  };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeser<'de> {
+  type Error = DeError;
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+      Cow::Owned(s) => visitor.visit_string(s),
+    }
+  }
+
+  parse_key! {
+    u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 bool char
+  }
+
+  fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Cow::Borrowed(s) => visitor.visit_borrowed_bytes(s.as_bytes()),
+      Cow::Owned(s) => visitor.visit_byte_buf(s.into_bytes()),
+    }
+  }
+  fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_bytes(visitor)
+  }
+
+  fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_newtype_struct<V>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    // Only unit variants can be keys
+    match self.0 {
+      Cow::Borrowed(s) => visitor.visit_enum(s.into_deserializer()),
+      Cow::Owned(s) => visitor.visit_enum(s.into_deserializer()),
+    }
+  }
+
+  forward_to_deserialize_any! {
+    str string identifier unit unit_struct seq tuple tuple_struct map
+    struct ignored_any
+  }
+}
@@ -1,15 +1,47 @@
 #![doc = include_str!("../README.md")]
 
+pub mod anchor;
+mod annotation;
+mod encoding;
+mod example;
+pub mod include;
+pub mod inherit;
+mod interpolate;
+pub mod jik;
+mod key;
 mod literal;
+pub mod merge;
 mod node;
+mod numeric;
+mod options;
+mod query;
+mod raw;
+mod reflect;
+pub mod schema;
+mod serialize;
+mod transcode;
+mod value;
+pub mod xik;
 
+pub use example::example;
 pub use literal::KdlAnnotatedValueDeser;
 pub use node::KdlNodeDeser;
+pub use numeric::NumericPolicy;
+pub use options::{AnnotationHandler, DeOptions};
+pub use query::{query, query_with, Query, QueryError};
+pub use raw::RawNode;
+#[cfg(feature = "serde_json")]
+pub use transcode::to_json_value;
+pub use transcode::{transcode, transcode_document};
+pub use value::Value;
 
 use std::{char::CharTryFromError, convert::Infallible, num::TryFromIntError};
 
-use kdl::{KdlEntry, KdlNode, KdlValue};
-use serde::{de, Deserialize};
+use kdl::{KdlDocument, KdlEntry, KdlError, KdlNode, KdlValue};
+use serde::{
+  de::{self, DeserializeSeed},
+  ser, Deserialize,
+};
 use thiserror::Error;
 
 /// Deserialize a `KdlNode`.
@@ -20,6 +52,84 @@ pub fn deserialize_node<'de, T: Deserialize<'de>>(
   T::deserialize(deserializer)
 }
 
+/// Deserialize a `KdlNode` with the given options.
+pub fn deserialize_node_with<'de, T: Deserialize<'de>>(
+  kdl: &'de KdlNode,
+  options: &'de DeOptions,
+) -> Result<T, DeError> {
+  let deserializer = KdlNodeDeser::with_options(kdl, options);
+  T::deserialize(deserializer)
+}
+
+/// Deserialize a `KdlNode` with a `DeserializeSeed`, for when you need
+/// to carry some context around while deserializing.
+///
+/// To get at child nodes from inside your seed, deserialize a [`RawNode`]
+/// and use [`RawNode::deserializer`], or take the [`KdlNodeDeser`] directly.
+pub fn deserialize_node_seed<'de, S: DeserializeSeed<'de>>(
+  seed: S,
+  kdl: &'de KdlNode,
+) -> Result<S::Value, DeError> {
+  let deserializer = KdlNodeDeser::new(kdl);
+  seed.deserialize(deserializer)
+}
+
+/// Deserialize a `KdlNode` with a `DeserializeSeed` and the given options.
+pub fn deserialize_node_seed_with<'de, S: DeserializeSeed<'de>>(
+  seed: S,
+  kdl: &'de KdlNode,
+  options: &'de DeOptions,
+) -> Result<S::Value, DeError> {
+  let deserializer = KdlNodeDeser::with_options(kdl, options);
+  seed.deserialize(deserializer)
+}
+
+/// Deserialize an `Option<Option<T>>` so that a `null` is `Some(None)`,
+/// instead of serde's default of flattening it into `None`.
+///
+/// Use it with `#[serde(default, deserialize_with = "knurdy::double_option")]`
+/// to tell a missing field (`None`) from one that's explicitly `null`
+/// (`Some(None)`).
+pub fn double_option<'de, T, D>(
+  deserializer: D,
+) -> Result<Option<Option<T>>, D::Error>
+where
+  T: Deserialize<'de>,
+  D: de::Deserializer<'de>,
+{
+  Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Which version of the KDL spec to parse a document as.
+///
+/// Both versions parse into the same data model, so a document from either
+/// one deserializes the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KdlVersion {
+  /// Try v2, and if that fails try v1. Without the `v1` feature this is
+  /// the same as `V2`.
+  #[default]
+  Detect,
+  #[cfg(feature = "v1")]
+  V1,
+  V2,
+}
+
+/// Parse a `KdlDocument` as the given version of KDL.
+///
+/// This is the same as `str::parse` for `KdlVersion::Detect`.
+pub fn parse_document(
+  text: &str,
+  version: KdlVersion,
+) -> Result<KdlDocument, KdlError> {
+  match version {
+    KdlVersion::Detect => KdlDocument::parse(text),
+    #[cfg(feature = "v1")]
+    KdlVersion::V1 => KdlDocument::parse_v1(text),
+    KdlVersion::V2 => KdlDocument::parse_v2(text),
+  }
+}
+
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum DeError {
   #[error("the deserialize impl on the type reported an error: {0}")]
@@ -34,20 +144,72 @@ pub enum DeError {
     got: usize,
     type_name: &'static str,
   },
+  /// An integer didn't fit its type under [`NumericPolicy::Lenient`]
   #[error("could not turn fit the given int into the target size: {0}")]
   IntSize(#[from] TryFromIntError),
   #[error("could not interpret the int as a char: {0}")]
   InvalidChar(#[from] CharTryFromError),
   #[error("could not decode base64: {0}")]
   Base64Error(#[from] base64::DecodeError),
+  #[error("could not decode {encoding}: {reason}")]
+  BytesDecode {
+    encoding: &'static str,
+    reason: String,
+  },
 
   #[error("a string must be 1 byte long to be interpreted as a u8")]
   ByteAnnotationLen,
   #[error("a string must be 1 char long to be interpreted as a char")]
   CharAnnotationLen,
 
+  #[error("{value} doesn't match its `({annotation})` annotation: {reason}")]
+  AnnotationMismatch {
+    annotation: String,
+    value: String,
+    reason: &'static str,
+  },
+
+  /// A number would lose precision as `target`. Under the stricter
+  /// [`NumericPolicy`]s, this is also what integers that don't fit give.
+  #[error("{value} can't be represented exactly as {target}")]
+  LossyNumber { value: String, target: &'static str },
+  #[error("float {value} can't be deserialized as {target}")]
+  FloatToInt { value: String, target: &'static str },
+  #[error("float {value} isn't a whole number, so it can't be deserialized as {target}")]
+  NonIntegralFloat { value: String, target: &'static str },
+
+  #[error("`${{{0}}}` isn't defined")]
+  UndefinedVariable(String),
+  #[error("{0:?} has a `${{` with no `}}`")]
+  UnclosedInterpolation(String),
+
+  #[error(
+    "bare node `{node}` doesn't name a variant of `{enum_name}`, so give the \
+     variant as an argument"
+  )]
+  BareEnumNode {
+    node: String,
+    enum_name: &'static str,
+  },
+
+  #[error("invalid JiK node: {0}")]
+  InvalidJik(&'static str),
+
+  #[error(
+    "a `RawNode` has to be deserialized straight from a `KdlNodeDeser`, \
+     not through `#[serde(flatten)]` or an untagged or internally tagged enum"
+  )]
+  RawNodeOutOfPlace,
+
   #[error("{0}")]
   MismatchedType(String),
+
+  /// From deserializing a [`Merged`](merge::Merged) document
+  #[error("in the `{layer}` layer: {source}")]
+  InLayer {
+    layer: String,
+    source: Box<DeError>,
+  },
 }
 
 impl de::Error for DeError {
@@ -59,6 +221,25 @@ impl de::Error for DeError {
   }
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SerError {
+  #[error("the serialize impl on the type reported an error: {0}")]
+  SerializeError(String),
+  #[error(
+    "map keys must be strings, integers, bools, chars or unit enum variants"
+  )]
+  InvalidKey,
+}
+
+impl ser::Error for SerError {
+  fn custom<T>(msg: T) -> Self
+  where
+    T: std::fmt::Display,
+  {
+    Self::SerializeError(msg.to_string())
+  }
+}
+
 impl From<Infallible> for DeError {
   fn from(_: Infallible) -> Self {
     unreachable!()
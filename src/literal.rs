@@ -1,11 +1,20 @@
-use crate::{DeError, KdlAnnotatedValueWrap};
+use crate::{
+  annotation::{self, BigInt},
+  encoding,
+  interpolate::interpolate,
+  numeric::{self, NumericPolicy},
+  value::{Value, VALUE_TOKEN},
+  DeError, DeOptions, KdlAnnotatedValueWrap,
+};
 
-use std::convert::TryInto;
+use std::{borrow::Cow, convert::TryInto};
 
 use kdl::{KdlEntry, KdlValue};
 use serde::de::{self, Error, IntoDeserializer, Unexpected, Visitor};
+use serde::Deserialize;
 
-/// Use KdlLiteralDeser and ignore the annotation data
+/// Use KdlLiteralDeser and ignore the annotation data (after checking it),
+/// unless there's a custom handler for the annotation
 macro_rules! ignore_annotation_to_literal {
   (@ $ty:ident) => {
     paste::paste! {
@@ -13,7 +22,10 @@ macro_rules! ignore_annotation_to_literal {
       where
         V: Visitor<'de>,
       {
-        KdlLiteralDeser(self.0.value).[< deserialize_ $ty >](visitor)
+        if let Some(converted) = self.convert()? {
+          return converted.[< deserialize_ $ty >](visitor);
+        }
+        KdlLiteralDeser(self.0.value, self.1).[< deserialize_ $ty >](visitor)
       }
     }
   };
@@ -23,6 +35,37 @@ macro_rules! ignore_annotation_to_literal {
     )*
   };
 }
+/// Integers can also be strings annotated with their type, for ones too
+/// big for KDL
+macro_rules! annotated_int {
+  (@ $ty:ident) => {
+    paste::paste! {
+      fn [< deserialize_ $ty >]<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+      where
+        V: Visitor<'de>,
+      {
+        if let Some(converted) = self.convert()? {
+          return converted.[< deserialize_ $ty >](visitor);
+        }
+        let policy = self.1.numeric_policy();
+        match self.string_int()? {
+          Some(BigInt::Negative(it)) => {
+            visitor.[< visit_ $ty >](numeric::narrow(it, stringify!($ty), policy)?)
+          }
+          Some(BigInt::Positive(it)) => {
+            visitor.[< visit_ $ty >](numeric::narrow(it, stringify!($ty), policy)?)
+          }
+          None => KdlLiteralDeser(self.0.value, self.1).[< deserialize_ $ty >](visitor),
+        }
+      }
+    }
+  };
+  ( $($ty:ident)* ) => {
+    $(
+      annotated_int!(@ $ty);
+    )*
+  };
+}
 macro_rules! deser_int_literal {
   (@ $ty:ty) => {
     paste::paste! {
@@ -31,8 +74,16 @@ macro_rules! deser_int_literal {
         V: Visitor<'de>,
       {
         match self.0 {
-          KdlValue::Base2(it) | KdlValue::Base8(it) | KdlValue::Base10(it) | KdlValue::Base16(it) => {
-            let squished: $ty = (*it).try_into()?;
+          KdlValue::Integer(it) => {
+            let squished: $ty = numeric::narrow(*it, stringify!($ty), self.policy())?;
+            visitor.[< visit_ $ty >](squished)
+          }
+          KdlValue::Float(f) => {
+            let int = numeric::float_to_int(*f, stringify!($ty), self.policy())?;
+            let squished: $ty = match int {
+              BigInt::Negative(it) => numeric::narrow(it, stringify!($ty), self.policy())?,
+              BigInt::Positive(it) => numeric::narrow(it, stringify!($ty), self.policy())?,
+            };
             visitor.[< visit_ $ty >](squished)
           }
           oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
@@ -47,14 +98,44 @@ macro_rules! deser_int_literal {
   };
 }
 
+/// Visit with the smallest type the int fits in
+pub(crate) fn visit_big_int<'de, V>(
+  int: BigInt,
+  visitor: V,
+) -> Result<V::Value, DeError>
+where
+  V: Visitor<'de>,
+{
+  match int {
+    BigInt::Negative(it) => match i64::try_from(it) {
+      Ok(it) => visitor.visit_i64(it),
+      Err(_) => visitor.visit_i128(it),
+    },
+    BigInt::Positive(it) => {
+      if let Ok(it) = i64::try_from(it) {
+        visitor.visit_i64(it)
+      } else if let Ok(it) = u64::try_from(it) {
+        visitor.visit_u64(it)
+      } else {
+        visitor.visit_u128(it)
+      }
+    }
+  }
+}
+
 fn unexpected_val(val: &KdlValue) -> Unexpected<'_> {
   match val {
-    KdlValue::String(s) | KdlValue::RawString(s) => Unexpected::Str(s),
-    KdlValue::Base2(it)
-    | KdlValue::Base8(it)
-    | KdlValue::Base10(it)
-    | KdlValue::Base16(it) => Unexpected::Signed(*it),
-    KdlValue::Base10Float(f) => Unexpected::Float(*f),
+    KdlValue::String(s) => Unexpected::Str(s),
+    KdlValue::Integer(it) => {
+      if let Ok(it) = i64::try_from(*it) {
+        Unexpected::Signed(it)
+      } else if let Ok(it) = u64::try_from(*it) {
+        Unexpected::Unsigned(it)
+      } else {
+        Unexpected::Other("128-bit integer")
+      }
+    }
+    KdlValue::Float(f) => Unexpected::Float(*f),
     KdlValue::Bool(b) => Unexpected::Bool(*b),
     KdlValue::Null => Unexpected::Unit,
   }
@@ -64,22 +145,54 @@ fn unexpected_val(val: &KdlValue) -> Unexpected<'_> {
 ///
 /// This is mostly used internally.
 #[derive(Debug, Clone, Copy)]
-pub struct KdlAnnotatedValueDeser<'de>(pub(crate) KdlAnnotatedValueWrap<'de>);
+pub struct KdlAnnotatedValueDeser<'de>(
+  pub(crate) KdlAnnotatedValueWrap<'de>,
+  pub(crate) &'de DeOptions,
+);
 
 impl<'de> KdlAnnotatedValueDeser<'de> {
   pub fn new(entry: &'de KdlEntry) -> Self {
-    Self(KdlAnnotatedValueWrap::from_entry(&entry))
+    Self::with_options(entry, DeOptions::default_ref())
   }
 
-  fn annotation_is(&self, s: &str) -> bool {
-    match self.0.annotation {
-      Some(it) => it == s,
-      None => false,
+  pub fn with_options(entry: &'de KdlEntry, options: &'de DeOptions) -> Self {
+    Self(KdlAnnotatedValueWrap::from_entry(entry), options)
+  }
+
+  /// Integers too big for KDL, like `(u64)"18446744073709551615"`
+  fn string_int(&self) -> Result<Option<BigInt>, DeError> {
+    let Some(ann) = self.0.annotation else {
+      return Ok(None);
+    };
+    annotation::string_int(ann, self.0.value)
+      .transpose()
+      .map_err(|reason| DeError::AnnotationMismatch {
+        annotation: ann.to_owned(),
+        value: self.0.value.to_string(),
+        reason,
+      })
+  }
+
+  fn string_float(&self) -> Option<f64> {
+    annotation::string_float(self.0.annotation?, self.0.value)
+  }
+
+  /// If there's a custom handler for the annotation, run it and return what
+  /// should be deserialized instead.
+  /// Otherwise, make sure the value matches its annotation, if it's one of
+  /// the ones reserved by the spec.
+  fn convert(&self) -> Result<Option<Value>, DeError> {
+    let Some(ann) = self.0.annotation else {
+      return Ok(None);
+    };
+    match self.1.annotation_handler(ann) {
+      Some(handler) => handler(self.0.value).map(Some),
+      None => annotation::check_reserved(ann, self.0.value).map(|()| None),
     }
   }
 }
 
-struct KdlLiteralDeser<'de>(&'de KdlValue);
+struct KdlLiteralDeser<'de>(&'de KdlValue, &'de DeOptions);
 
 impl<'de> de::Deserializer<'de> for KdlAnnotatedValueDeser<'de> {
   type Error = DeError;
@@ -88,36 +201,80 @@ impl<'de> de::Deserializer<'de> for KdlAnnotatedValueDeser<'de> {
   where
     V: de::Visitor<'de>,
   {
+    if let Some(converted) = self.convert()? {
+      return converted.deserialize_any(visitor);
+    }
+    if let Some(int) = self.string_int()? {
+      return visit_big_int(int, visitor);
+    }
+    if let Some(f) = self.string_float() {
+      return visitor.visit_f64(f);
+    }
     match self.0.value {
-      KdlValue::String(_) | KdlValue::RawString(_) => {
-        self.deserialize_str(visitor)
-      }
-      KdlValue::Base2(_)
-      | KdlValue::Base8(_)
-      | KdlValue::Base10(_)
-      | KdlValue::Base16(_) => self.deserialize_i64(visitor),
-      KdlValue::Base10Float(_) => self.deserialize_f64(visitor),
+      KdlValue::String(_) => self.deserialize_str(visitor),
+      KdlValue::Integer(it) => visit_big_int(BigInt::from(*it), visitor),
+      KdlValue::Float(_) => self.deserialize_f64(visitor),
       KdlValue::Bool(_) => self.deserialize_bool(visitor),
       KdlValue::Null => self.deserialize_unit(visitor),
     }
   }
 
   ignore_annotation_to_literal! {
-      u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 bool
-      char str string identifier unit seq map ignored_any
+      bool char str string identifier unit seq map ignored_any
+  }
+  annotated_int! {
+      u8 u16 u32 u64 u128 i8 i16 i32 i64 i128
+  }
+
+  fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    if let Some(converted) = self.convert()? {
+      return converted.deserialize_f32(visitor);
+    }
+    if let Some(f) = self.string_float() {
+      return visitor.visit_f32(f as f32);
+    }
+    match self.string_int()? {
+      Some(int) => {
+        let policy = self.1.numeric_policy();
+        visitor.visit_f32(numeric::int_to_f32(int, policy)?)
+      }
+      None => KdlLiteralDeser(self.0.value, self.1).deserialize_f32(visitor),
+    }
+  }
+  fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    if let Some(converted) = self.convert()? {
+      return converted.deserialize_f64(visitor);
+    }
+    if let Some(f) = self.string_float() {
+      return visitor.visit_f64(f);
+    }
+    match self.string_int()? {
+      Some(int) => {
+        let policy = self.1.numeric_policy();
+        visitor.visit_f64(numeric::int_to_f64(int, policy)?)
+      }
+      None => KdlLiteralDeser(self.0.value, self.1).deserialize_f64(visitor),
+    }
   }
 
   fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
   where
     V: Visitor<'de>,
   {
+    if let Some(converted) = self.convert()? {
+      return converted.deserialize_bytes(visitor);
+    }
     match &self.0.value {
-      KdlValue::String(s) | KdlValue::RawString(s) => {
-        if self.annotation_is("base64") {
-          let b64 = base64::decode(s.as_str())?;
-          visitor.visit_byte_buf(b64)
-        } else {
-          visitor.visit_bytes(s.as_bytes())
+      KdlValue::String(s) => {
+        match self.0.annotation.and_then(|ann| encoding::decode(ann, s)) {
+          Some(decoded) => visitor.visit_byte_buf(decoded?),
+          None => visitor.visit_bytes(s.as_bytes()),
         }
       }
       oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
@@ -134,6 +291,9 @@ impl<'de> de::Deserializer<'de> for KdlAnnotatedValueDeser<'de> {
   where
     V: Visitor<'de>,
   {
+    if let Some(converted) = self.convert()? {
+      return converted.deserialize_option(visitor);
+    }
     match &self.0.value {
       KdlValue::Null => visitor.visit_none(),
       _ => visitor.visit_some(self),
@@ -144,24 +304,32 @@ impl<'de> de::Deserializer<'de> for KdlAnnotatedValueDeser<'de> {
   // Non-unit enums are parsed with the annotation as the variant.
   fn deserialize_enum<V>(
     self,
-    _name: &'static str,
-    _variants: &'static [&'static str],
+    name: &'static str,
+    variants: &'static [&'static str],
     visitor: V,
   ) -> Result<V::Value, Self::Error>
   where
     V: Visitor<'de>,
   {
+    if let Some(handler) =
+      self.0.annotation.and_then(|ann| self.1.annotation_handler(ann))
+    {
+      return handler(self.0.value)?
+        .deserialize_enum(name, variants, visitor);
+    }
     let (variant, value) = match (self.0.annotation, &self.0.value) {
       // Unit variant
-      (None, KdlValue::String(s) | KdlValue::RawString(s)) => {
-        (s.as_str(), None)
-      }
+      (None, KdlValue::String(s)) => (s.as_str(), None),
       (None, oh_no) => {
-        return Err(DeError::invalid_type(unexpected_val(*oh_no), &visitor))
+        return Err(DeError::invalid_type(unexpected_val(oh_no), &visitor))
       }
       (Some(ann), v) => (ann, Some(*v)),
     };
-    visitor.visit_enum(EnumLiteralDeserializer { variant, value })
+    visitor.visit_enum(EnumLiteralDeserializer {
+      variant,
+      value,
+      options: self.1,
+    })
   }
 
   // other passthrus that i can't do with the easy macro
@@ -177,13 +345,34 @@ impl<'de> de::Deserializer<'de> for KdlAnnotatedValueDeser<'de> {
   }
   fn deserialize_newtype_struct<V>(
     self,
-    _name: &'static str,
+    name: &'static str,
     visitor: V,
   ) -> Result<V::Value, Self::Error>
   where
     V: Visitor<'de>,
   {
-    visitor.visit_newtype_struct(self)
+    if let Some(converted) = self.convert()? {
+      return converted.deserialize_newtype_struct(name, visitor);
+    }
+    match self.0.annotation {
+      // A `Value` wants to keep the annotation around
+      Some(ann) if name == VALUE_TOKEN => {
+        // Keep the string if it's too big for a `Value::Int`
+        let inner = match self.string_int()? {
+          Some(BigInt::Negative(it)) => Value::Int(it),
+          Some(BigInt::Positive(it)) if it <= i128::MAX as u128 => {
+            Value::Int(it as i128)
+          }
+          _ => match self.string_float() {
+            Some(f) => Value::Float(f),
+            None => Value::deserialize(KdlLiteralDeser(self.0.value, self.1))?,
+          },
+        };
+        Value::Annotated(ann.to_owned(), Box::new(inner))
+          .deserialize_newtype_struct(name, visitor)
+      }
+      _ => visitor.visit_newtype_struct(self),
+    }
   }
   fn deserialize_tuple<V>(
     self,
@@ -219,6 +408,12 @@ impl<'de> de::Deserializer<'de> for KdlAnnotatedValueDeser<'de> {
   }
 }
 
+impl<'de> KdlLiteralDeser<'de> {
+  fn policy(&self) -> NumericPolicy {
+    self.1.numeric_policy()
+  }
+}
+
 impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
   type Error = DeError;
 
@@ -227,14 +422,9 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: de::Visitor<'de>,
   {
     match self.0 {
-      KdlValue::String(_) | KdlValue::RawString(_) => {
-        self.deserialize_str(visitor)
-      }
-      KdlValue::Base2(_)
-      | KdlValue::Base8(_)
-      | KdlValue::Base10(_)
-      | KdlValue::Base16(_) => self.deserialize_i64(visitor),
-      KdlValue::Base10Float(_) => self.deserialize_f64(visitor),
+      KdlValue::String(_) => self.deserialize_str(visitor),
+      KdlValue::Integer(it) => visit_big_int(BigInt::from(*it), visitor),
+      KdlValue::Float(_) => self.deserialize_f64(visitor),
       KdlValue::Bool(_) => self.deserialize_bool(visitor),
       KdlValue::Null => self.deserialize_unit(visitor),
     }
@@ -248,15 +438,20 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: Visitor<'de>,
   {
     match self.0 {
-      KdlValue::String(s) | KdlValue::RawString(s) => match s.as_bytes() {
+      KdlValue::String(s) => match s.as_bytes() {
         [b] => visitor.visit_u8(*b),
         _ => Err(DeError::ByteAnnotationLen),
       },
-      KdlValue::Base2(it)
-      | KdlValue::Base8(it)
-      | KdlValue::Base10(it)
-      | KdlValue::Base16(it) => {
-        let squished: u8 = (*it).try_into()?;
+      KdlValue::Integer(it) => {
+        let squished: u8 = numeric::narrow(*it, "u8", self.policy())?;
+        visitor.visit_u8(squished)
+      }
+      KdlValue::Float(f) => {
+        let squished: u8 = match numeric::float_to_int(*f, "u8", self.policy())?
+        {
+          BigInt::Negative(it) => numeric::narrow(it, "u8", self.policy())?,
+          BigInt::Positive(it) => numeric::narrow(it, "u8", self.policy())?,
+        };
         visitor.visit_u8(squished)
       }
       oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
@@ -267,7 +462,7 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: Visitor<'de>,
   {
     match self.0 {
-      KdlValue::String(s) | KdlValue::RawString(s) => {
+      KdlValue::String(s) => {
         let mut chars = s.chars();
         let ch0 = chars.next();
         let ch1 = chars.next();
@@ -276,11 +471,8 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
           _ => Err(DeError::CharAnnotationLen),
         }
       }
-      KdlValue::Base2(it)
-      | KdlValue::Base8(it)
-      | KdlValue::Base10(it)
-      | KdlValue::Base16(it) => {
-        let squished: u32 = (*it).try_into()?;
+      KdlValue::Integer(it) => {
+        let squished: u32 = numeric::narrow(*it, "char", self.policy())?;
         let squished_again: char = squished.try_into()?;
         visitor.visit_char(squished_again)
       }
@@ -292,14 +484,12 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: Visitor<'de>,
   {
     match self.0 {
-      KdlValue::Base10Float(f) => {
-        // For some reason there doesn't seem to be Into or TryInto impls for f64 => f32?
-        visitor.visit_f32(*f as f32)
+      KdlValue::Float(f) => {
+        visitor.visit_f32(numeric::f64_to_f32(*f, self.policy())?)
+      }
+      KdlValue::Integer(it) => {
+        visitor.visit_f32(numeric::int_to_f32((*it).into(), self.policy())?)
       }
-      KdlValue::Base2(it)
-      | KdlValue::Base8(it)
-      | KdlValue::Base10(it)
-      | KdlValue::Base16(it) => visitor.visit_f32(*it as f32),
       oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
     }
   }
@@ -308,11 +498,10 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: Visitor<'de>,
   {
     match self.0 {
-      KdlValue::Base10Float(f) => visitor.visit_f64(*f),
-      KdlValue::Base2(it)
-      | KdlValue::Base8(it)
-      | KdlValue::Base10(it)
-      | KdlValue::Base16(it) => visitor.visit_f64(*it as f64),
+      KdlValue::Float(f) => visitor.visit_f64(*f),
+      KdlValue::Integer(it) => {
+        visitor.visit_f64(numeric::int_to_f64((*it).into(), self.policy())?)
+      }
       oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
     }
   }
@@ -333,9 +522,10 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: Visitor<'de>,
   {
     match self.0 {
-      KdlValue::String(s) | KdlValue::RawString(s) => {
-        visitor.visit_str(s.as_str())
-      }
+      KdlValue::String(s) => match interpolate(s, self.1)? {
+        Cow::Borrowed(s) => visitor.visit_str(s),
+        Cow::Owned(s) => visitor.visit_string(s),
+      },
       oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
     }
   }
@@ -351,9 +541,7 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
     V: Visitor<'de>,
   {
     match self.0 {
-      KdlValue::String(s) | KdlValue::RawString(s) => {
-        visitor.visit_bytes(s.as_bytes())
-      }
+      KdlValue::String(s) => visitor.visit_bytes(s.as_bytes()),
       oh_no => Err(DeError::invalid_type(unexpected_val(oh_no), &visitor)),
     }
   }
@@ -495,6 +683,7 @@ impl<'de> de::Deserializer<'de> for KdlLiteralDeser<'de> {
 struct EnumLiteralDeserializer<'a> {
   variant: &'a str,
   value: Option<&'a KdlValue>,
+  options: &'a DeOptions,
 }
 
 impl<'de> de::EnumAccess<'de> for EnumLiteralDeserializer<'de> {
@@ -533,7 +722,7 @@ impl<'de> de::VariantAccess<'de> for EnumLiteralDeserializer<'de> {
   {
     match self.value {
       // Deserialize the newtype data
-      Some(value) => seed.deserialize(KdlLiteralDeser(value)),
+      Some(value) => seed.deserialize(KdlLiteralDeser(value, self.options)),
       None => Err(DeError::invalid_type(
         Unexpected::UnitVariant,
         &"annotated literal",
@@ -0,0 +1,427 @@
+//! Merging documents in layers, like defaults, then mods, then user
+//! overrides, while keeping track of which layer each value came from.
+//!
+//! Each layer is merged on top of everything before it:
+//!
+//! - Properties replace earlier properties with the same name. A property
+//!   annotated `(remove)`, like `speed=(remove)#null`, removes the earlier
+//!   one instead.
+//! - Children are matched up by name (the first `item` with the earlier
+//!   first `item`, and so on) and merged the same way. Ones without a match
+//!   go at the end. Top-level nodes are matched up like children.
+//! - Arguments and `-` children are sequences, and by [`SeqPolicy`] they
+//!   either replace all of the earlier ones, if there are any, or are
+//!   added after them.
+//! - `(append)` on a child adds its arguments and `-` children after the
+//!   earlier ones whatever the policy is.
+//! - `(override)` on a child replaces the earlier child outright.
+//! - `(remove)` on a child removes the earlier child.
+//!
+//! [`Merged::source`] looks up where a node or property came from, and
+//! [`Merged::deserialize_node`] puts the layer into any error, down to the
+//! argument that caused it.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::Arc,
+};
+
+use kdl::{KdlDocument, KdlNode};
+use serde::de::DeserializeOwned;
+
+use crate::{schema::with_paths, DeError, DeOptions};
+
+/// What a later layer's arguments and `-` children do to the earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SeqPolicy {
+  #[default]
+  Replace,
+  Append,
+}
+
+/// Documents to merge, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+  layers: Vec<(String, KdlDocument)>,
+  policy: SeqPolicy,
+}
+
+impl Layers {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_policy(mut self, policy: SeqPolicy) -> Self {
+    self.policy = policy;
+    self
+  }
+
+  /// Add a layer on top of the others, with a name to tell where values
+  /// came from.
+  pub fn with_layer(
+    mut self,
+    name: impl Into<String>,
+    doc: KdlDocument,
+  ) -> Self {
+    self.layers.push((name.into(), doc));
+    self
+  }
+
+  /// Add a layer holding just one node.
+  pub fn with_node_layer(self, name: impl Into<String>, node: KdlNode) -> Self {
+    let mut doc = KdlDocument::new();
+    doc.nodes_mut().push(node);
+    self.with_layer(name, doc)
+  }
+
+  pub fn merge(&self) -> Merged {
+    let mut root = KdlNode::new("-");
+    let mut origin = Origin::new(0);
+    for (layer, (_, doc)) in self.layers.iter().enumerate() {
+      let mut top = KdlNode::new("-");
+      top.set_children(doc.clone());
+      merge_into(&mut root, &mut origin, &top, layer, self.policy);
+    }
+
+    let mut document = root.children().cloned().unwrap_or_default();
+    document.autoformat();
+    let names = self.layers.iter().map(|(name, _)| name.clone()).collect();
+    Merged::new(document, names, origin.kids)
+  }
+}
+
+/// The result of merging some [`Layers`].
+#[derive(Debug)]
+pub struct Merged {
+  document: KdlDocument,
+  names: Vec<String>,
+  sources: BTreeMap<String, usize>,
+  origins: Vec<Origin>,
+  /// Layers by the address of each node and value in `document`, which
+  /// stay put since it's never changed
+  provenance: Arc<Provenance>,
+}
+
+impl Clone for Merged {
+  fn clone(&self) -> Self {
+    // The copy's nodes are at new addresses
+    Self::new(self.document.clone(), self.names.clone(), self.origins.clone())
+  }
+}
+
+impl Merged {
+  fn new(
+    document: KdlDocument,
+    names: Vec<String>,
+    origins: Vec<Origin>,
+  ) -> Self {
+    let mut sources = BTreeMap::new();
+    record(document.nodes(), &origins, "/", &mut sources);
+    let provenance = Arc::new(Provenance::new(&document, &origins, &names));
+    Self {
+      document,
+      names,
+      sources,
+      origins,
+      provenance,
+    }
+  }
+
+  /// The merged document, ready to deserialize.
+  pub fn document(&self) -> &KdlDocument {
+    &self.document
+  }
+
+  /// The name of the layer the value at `path` came from.
+  ///
+  /// Paths look like the ones in [schema violations](crate::schema), like
+  /// `/config/window/width`, and name either a node or a property. A node
+  /// came from the last layer that set its arguments, or else the one that
+  /// added it.
+  pub fn source(&self, path: &str) -> Option<&str> {
+    let layer = *self.sources.get(path)?;
+    Some(&self.names[layer])
+  }
+
+  /// Deserialize a node from [`document`](Self::document), with errors
+  /// wrapped in [`DeError::InLayer`] to say which layer the value or node
+  /// that caused them came from.
+  ///
+  /// Layers are looked up by where things are in memory, so `node` has to
+  /// be borrowed straight out of `document`. A node cloned out of it still
+  /// deserializes, but its errors won't name any layer.
+  pub fn deserialize_node<T: DeserializeOwned>(
+    &self,
+    node: &KdlNode,
+  ) -> Result<T, DeError> {
+    self.deserialize_node_with(node, DeOptions::default_ref())
+  }
+
+  /// Deserialize a node from [`document`](Self::document) with the given
+  /// options, with errors saying which layer they came from. Like
+  /// [`deserialize_node`](Self::deserialize_node), `node` has to be
+  /// borrowed from the document.
+  pub fn deserialize_node_with<T: DeserializeOwned>(
+    &self,
+    node: &KdlNode,
+    options: &DeOptions,
+  ) -> Result<T, DeError> {
+    let options = options.clone().with_provenance(self.provenance.clone());
+    let res = crate::deserialize_node_with(node, &options);
+    options.locate(node, res)
+  }
+
+  /// Every path in the merged document, along with where it came from.
+  pub fn sources(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .sources
+      .iter()
+      .map(|(path, layer)| (path.as_str(), self.names[*layer].as_str()))
+  }
+}
+
+fn record(
+  nodes: &[KdlNode],
+  origins: &[Origin],
+  path: &str,
+  out: &mut BTreeMap<String, usize>,
+) {
+  for ((node, path), origin) in with_paths(nodes, path).zip(origins) {
+    for (key, layer) in &origin.props {
+      out.insert(format!("{}/{}", path, key), *layer);
+    }
+    out.insert(path.clone(), origin.node);
+    let kids = node.children().map_or(&[][..], |it| it.nodes());
+    record(kids, &origin.kids, &path, out);
+  }
+}
+
+/// Which layer each node and value in a merged document came from, by
+/// address, for putting in errors.
+#[derive(Debug)]
+pub(crate) struct Provenance {
+  layers: HashMap<usize, usize>,
+  names: Vec<String>,
+}
+
+impl Provenance {
+  fn new(document: &KdlDocument, origins: &[Origin], names: &[String]) -> Self {
+    let mut layers = HashMap::new();
+    locate_all(document.nodes(), origins, &mut layers);
+    Self {
+      layers,
+      names: names.to_vec(),
+    }
+  }
+
+  /// Wrap `err` in the layer `item` came from, unless it's already wrapped
+  pub(crate) fn locate<I>(&self, item: &I, err: DeError) -> DeError {
+    if matches!(err, DeError::InLayer { .. }) {
+      return err;
+    }
+    match self.layers.get(&address(item)) {
+      Some(&layer) => DeError::InLayer {
+        layer: self.names[layer].clone(),
+        source: Box::new(err),
+      },
+      None => err,
+    }
+  }
+}
+
+fn address<I>(item: &I) -> usize {
+  item as *const I as usize
+}
+
+fn locate_all(
+  nodes: &[KdlNode],
+  origins: &[Origin],
+  out: &mut HashMap<usize, usize>,
+) {
+  for (node, origin) in nodes.iter().zip(origins) {
+    out.insert(address(node), origin.node);
+    let mut args = origin.args.iter();
+    for entry in node.entries() {
+      let layer = match entry.name() {
+        Some(key) => origin.props.get(key.value()),
+        None => args.next(),
+      };
+      if let Some(&layer) = layer {
+        out.insert(address(entry.value()), layer);
+      }
+    }
+    let kids = node.children().map_or(&[][..], |it| it.nodes());
+    locate_all(kids, &origin.kids, out);
+  }
+}
+
+/// Which layer each part of a node came from, alongside it.
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+  node: usize,
+  args: Vec<usize>,
+  props: HashMap<String, usize>,
+  kids: Vec<Origin>,
+}
+
+impl Origin {
+  pub(crate) fn new(layer: usize) -> Self {
+    Self {
+      node: layer,
+      args: Vec::new(),
+      props: HashMap::new(),
+      kids: Vec::new(),
+    }
+  }
+
+  /// Everything in `node` coming from `layer`
+  pub(crate) fn of(node: &KdlNode, layer: usize) -> Self {
+    let props = node
+      .entries()
+      .iter()
+      .filter_map(|it| it.name())
+      .map(|it| (it.value().to_owned(), layer))
+      .collect();
+    let args = node.entries().iter().filter(|it| it.name().is_none());
+    let kids = node.children().map_or(&[][..], |it| it.nodes());
+    Self {
+      node: layer,
+      args: args.map(|_| layer).collect(),
+      props,
+      kids: kids.iter().map(|it| Origin::of(it, layer)).collect(),
+    }
+  }
+}
+
+/// Merge `layer` on top of `out`, as described in the [module docs](self).
+///
+/// This doesn't format anything, so call `autoformat` once it's done.
+pub(crate) fn merge_into(
+  out: &mut KdlNode,
+  origin: &mut Origin,
+  layer_node: &KdlNode,
+  layer: usize,
+  policy: SeqPolicy,
+) {
+  out.set_name(layer_node.name().clone());
+  if let Some(ty) = layer_node.ty().filter(|_| marker(layer_node).is_none())
+  {
+    *out.ty_mut() = Some(ty.clone());
+  }
+  let append =
+    policy == SeqPolicy::Append || marker(layer_node) == Some(Marker::Append);
+
+  // Entries
+  let args: Vec<_> = layer_node
+    .entries()
+    .iter()
+    .filter(|it| it.name().is_none())
+    .cloned()
+    .collect();
+  if !args.is_empty() {
+    if !append {
+      out.entries_mut().retain(|it| it.name().is_some());
+      origin.args.clear();
+    }
+    origin.args.extend(args.iter().map(|_| layer));
+    origin.node = layer;
+  }
+  out.entries_mut().extend(args);
+  for prop in layer_node.entries().iter().filter(|it| it.name().is_some()) {
+    let key = prop.name().map(|it| it.value());
+    let removing = prop.ty().map(|it| it.value()) == Some("remove");
+    let entries = out.entries_mut();
+    match entries.iter().rposition(|it| it.name().map(|it| it.value()) == key)
+    {
+      Some(_) if removing => {
+        entries.retain(|it| it.name().map(|it| it.value()) != key)
+      }
+      Some(idx) => entries[idx] = prop.clone(),
+      None if removing => {}
+      None => entries.push(prop.clone()),
+    }
+    let key = key.unwrap_or_default().to_owned();
+    if removing {
+      origin.props.remove(&key);
+    } else {
+      origin.props.insert(key, layer);
+    }
+  }
+
+  // Children
+  let Some(kids) = layer_node.children() else {
+    return;
+  };
+  let earlier = out.children().map(|it| it.nodes()).unwrap_or_default();
+  let names: Vec<_> =
+    earlier.iter().map(|it| it.name().value().to_owned()).collect();
+  let mut slots: Vec<_> = earlier
+    .iter()
+    .cloned()
+    .zip(origin.kids.drain(..))
+    .map(Some)
+    .collect();
+  let replacing_items = !append && kids.nodes().iter().any(is_item);
+  for (slot, name) in slots.iter_mut().zip(&names) {
+    if replacing_items && name == "-" {
+      *slot = None;
+    }
+  }
+
+  let fresh = |kid: &KdlNode| {
+    let mut node = KdlNode::new(kid.name().clone());
+    let mut origin = Origin::new(layer);
+    merge_into(&mut node, &mut origin, kid, layer, policy);
+    (node, origin)
+  };
+  let mut matched = HashMap::<&str, usize>::new();
+  for kid in kids.nodes() {
+    let name = kid.name().value();
+    if is_item(kid) {
+      slots.push(Some(fresh(kid)));
+      continue;
+    }
+    let nth = matched.entry(name).or_default();
+    let found = names
+      .iter()
+      .enumerate()
+      .filter(|(_, it)| *it == name)
+      .nth(*nth)
+      .map(|(idx, _)| idx);
+    *nth += 1;
+    match (found, marker(kid)) {
+      (Some(idx), Some(Marker::Remove)) => slots[idx] = None,
+      (Some(idx), Some(Marker::Override)) => slots[idx] = Some(fresh(kid)),
+      (Some(idx), _) => {
+        if let Some((node, origin)) = &mut slots[idx] {
+          merge_into(node, origin, kid, layer, policy);
+        }
+      }
+      (None, Some(Marker::Remove)) => {}
+      (None, _) => slots.push(Some(fresh(kid))),
+    }
+  }
+  let (nodes, origins) = slots.into_iter().flatten().unzip();
+  *out.ensure_children().nodes_mut() = nodes;
+  origin.kids = origins;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marker {
+  Override,
+  Append,
+  Remove,
+}
+
+fn marker(node: &KdlNode) -> Option<Marker> {
+  match node.ty()?.value() {
+    "override" => Some(Marker::Override),
+    "append" => Some(Marker::Append),
+    "remove" => Some(Marker::Remove),
+    _ => None,
+  }
+}
+
+fn is_item(node: &KdlNode) -> bool {
+  node.name().value() == "-"
+}
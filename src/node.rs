@@ -1,31 +1,105 @@
-use heck::ToSnekCase;
+use std::borrow::Cow;
+
+use heck::{ToKebabCase, ToSnekCase};
 use kdl::{KdlDocument, KdlEntry, KdlNode};
 use serde::de::{self, Error, IntoDeserializer, Unexpected};
+use serde::Deserialize;
 
-use crate::{literal::KdlAnnotatedValueDeser, DeError, KdlAnnotatedValueWrap};
+use crate::{
+  key::KeyDeser,
+  literal::KdlAnnotatedValueDeser,
+  numeric,
+  raw::{self, RAW_NODE_TOKEN},
+  value::{Value, VALUE_TOKEN},
+  DeError, DeOptions, KdlAnnotatedValueWrap,
+};
 
 /// Deserializer for a node
 #[derive(Debug, Clone)]
 pub struct KdlNodeDeser<'de> {
-  #[allow(dead_code)]
-  name: &'de str,
+  node: &'de KdlNode,
   entries: &'de [KdlEntry],
   children: Option<&'de KdlDocument>,
+  options: &'de DeOptions,
 
   forwarding_to_map_from_struct: bool,
 }
 
 impl<'de> KdlNodeDeser<'de> {
   pub fn new(wrapped: &'de KdlNode) -> Self {
+    Self::with_options(wrapped, DeOptions::default_ref())
+  }
+
+  pub fn with_options(wrapped: &'de KdlNode, options: &'de DeOptions) -> Self {
     Self {
-      name: wrapped.name().value(),
+      node: wrapped,
       entries: wrapped.entries(),
       children: wrapped.children(),
+      options,
 
       forwarding_to_map_from_struct: false,
     }
   }
 
+  pub fn options(&self) -> &'de DeOptions {
+    self.options
+  }
+
+  /// The node being deserialized
+  pub fn node(&self) -> &'de KdlNode {
+    self.node
+  }
+
+  pub fn name(&self) -> &'de str {
+    self.node.name().value()
+  }
+
+  /// Deserializers for each argument, in order.
+  pub fn arguments(
+    &self,
+  ) -> impl Iterator<Item = KdlAnnotatedValueDeser<'de>> + 'de {
+    let options = self.options;
+    self
+      .entries
+      .iter()
+      .filter(|entry| entry.name().is_none())
+      .map(move |entry| KdlAnnotatedValueDeser::with_options(entry, options))
+  }
+
+  /// Deserializers for each property, in order, along with their names.
+  pub fn properties(
+    &self,
+  ) -> impl Iterator<Item = (&'de str, KdlAnnotatedValueDeser<'de>)> + 'de {
+    let options = self.options;
+    self.entries.iter().filter_map(move |entry| {
+      entry.name().map(|name| {
+        (
+          name.value(),
+          KdlAnnotatedValueDeser::with_options(entry, options),
+        )
+      })
+    })
+  }
+
+  /// Deserializers for each child node, in order.
+  ///
+  /// Use these instead of `KdlNodeDeser::new` when writing your own
+  /// `DeserializeSeed`s so the children are deserialized the same way
+  /// their parent is.
+  pub fn children(&self) -> impl Iterator<Item = KdlNodeDeser<'de>> + 'de {
+    let options = self.options;
+    self
+      .children
+      .map_or(&[][..], |kids| kids.nodes())
+      .iter()
+      .map(move |kid| KdlNodeDeser::with_options(kid, options))
+  }
+
+  /// No arguments, properties or children
+  fn is_bare(&self) -> bool {
+    self.entries.is_empty() && self.children.is_none()
+  }
+
   fn collect_args_props(
     &self,
   ) -> (
@@ -45,6 +119,54 @@ impl<'de> KdlNodeDeser<'de> {
     }
     (args, props)
   }
+
+  /// A `Value` wants to know about us. Nodes it can't see with
+  /// `deserialize_any` get spelled out in full.
+  fn deserialize_value<V>(self, visitor: V) -> Result<V::Value, DeError>
+  where
+    V: de::Visitor<'de>,
+  {
+    let (arguments, properties) = self.collect_args_props();
+    if arguments.is_empty()
+      || (properties.is_empty() && self.children.is_none())
+    {
+      return de::Deserializer::deserialize_any(self, visitor);
+    }
+
+    let args = arguments
+      .into_iter()
+      .map(|arg| {
+        Value::deserialize(KdlAnnotatedValueDeser(arg, self.options))
+      })
+      .collect::<Result<_, _>>()?;
+    let props = properties
+      .into_iter()
+      .map(|(k, v)| {
+        let v = Value::deserialize(KdlAnnotatedValueDeser(v, self.options))?;
+        Ok((k.to_owned(), v))
+      })
+      .collect::<Result<_, DeError>>()?;
+    let children = self
+      .children
+      .map_or(&[][..], |kids| kids.nodes())
+      .iter()
+      .map(|kid| {
+        Ok((
+          kid.name().value().to_owned(),
+          Value::deserialize(KdlNodeDeser::with_options(kid, self.options))?,
+        ))
+      })
+      .collect::<Result<_, DeError>>()?;
+    de::Deserializer::deserialize_newtype_struct(
+      Value::Node {
+        args,
+        props,
+        children,
+      },
+      VALUE_TOKEN,
+      visitor,
+    )
+  }
 }
 
 macro_rules! single_scalar {
@@ -57,7 +179,9 @@ macro_rules! single_scalar {
         if let ([ref entry @ KdlEntry { .. }], true) = (self.entries, self.children.is_none()) {
           if entry.name().is_none() {
             // then it is actually an arg, not a prop
-            return KdlAnnotatedValueDeser::new(entry).[< deserialize_ $ty >](visitor);
+            let res = KdlAnnotatedValueDeser::with_options(entry, self.options)
+              .[< deserialize_ $ty >](visitor);
+            return self.options.locate(entry.value(), res);
           }
         }
 
@@ -83,8 +207,79 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
   type Error = DeError;
 
   single_scalar! {
-    u8 u16 u32 u64 i8 i16 i32 i64 char bool f32 f64
-    str string bytes byte_buf identifier
+    u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 char f32 f64
+    str string identifier
+  }
+
+  fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: de::Visitor<'de>,
+  {
+    // `hidden` on its own means `hidden #true`
+    if self.options.flag_nodes() && self.is_bare() {
+      return visitor.visit_bool(true);
+    }
+    if let ([ref entry @ KdlEntry { .. }], true) =
+      (self.entries, self.children.is_none())
+    {
+      if entry.name().is_none() {
+        let res = KdlAnnotatedValueDeser::with_options(entry, self.options)
+          .deserialize_bool(visitor);
+        return self.options.locate(entry.value(), res);
+      }
+    }
+    Err(DeError::invalid_type(
+      Unexpected::Other(
+        "node that isn't exactly one argument deserializable as bool \
+        and nothing else",
+      ),
+      &visitor,
+    ))
+  }
+
+  fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: de::Visitor<'de>,
+  {
+    // A list of integer arguments works as bytes
+    let all_ints = self.entries.iter().all(|entry| {
+      entry.name().is_none() && entry.value().is_integer()
+    });
+    if !self.entries.is_empty() && all_ints && self.children.is_none() {
+      let bytes = self
+        .entries
+        .iter()
+        .map(|entry| {
+          let it = entry.value().as_integer().unwrap();
+          let res = numeric::narrow(it, "u8", self.options.numeric_policy());
+          self.options.locate(entry.value(), res)
+        })
+        .collect::<Result<_, _>>()?;
+      return visitor.visit_byte_buf(bytes);
+    }
+
+    if let ([ref entry @ KdlEntry { .. }], true) =
+      (self.entries, self.children.is_none())
+    {
+      if entry.name().is_none() {
+        let res = KdlAnnotatedValueDeser::with_options(entry, self.options)
+          .deserialize_bytes(visitor);
+        return self.options.locate(entry.value(), res);
+      }
+    }
+    Err(DeError::invalid_type(
+      Unexpected::Other(
+        "node that isn't exactly one argument deserializable as bytes, \
+        or only integer arguments, and nothing else",
+      ),
+      &visitor,
+    ))
+  }
+  fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: de::Visitor<'de>,
+  {
+    self.deserialize_bytes(visitor)
   }
 
   fn deserialize_enum<V>(
@@ -96,13 +291,30 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
   where
     V: de::Visitor<'de>,
   {
+    // A bare node is the unit variant it's named after, so `full-screen`
+    // can be `FullScreen`
+    if self.options.flag_nodes() && self.is_bare() {
+      let node = self.name();
+      let variant = variants
+        .iter()
+        .find(|it| **it == node)
+        .or_else(|| variants.iter().find(|it| it.to_kebab_case() == node))
+        .ok_or_else(|| DeError::BareEnumNode {
+          node: node.to_owned(),
+          enum_name: name,
+        });
+      let res =
+        variant.and_then(|it| visitor.visit_enum(it.into_deserializer()));
+      return self.options.locate(self.node, res);
+    }
     if let ([ref entry @ KdlEntry { .. }], true) =
       (self.entries, self.children.is_none())
     {
       if entry.name().is_none() {
         // then it is actually an arg
-        return KdlAnnotatedValueDeser::new(entry)
+        let res = KdlAnnotatedValueDeser::with_options(entry, self.options)
           .deserialize_enum(name, variants, visitor);
+        return self.options.locate(entry.value(), res);
       }
     }
     Err(DeError::invalid_type(
@@ -139,10 +351,14 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
       (true, false) => {
         let mut args = arguments;
         args.reverse();
-        visitor.visit_seq(SeqArgsDeser(args))
+        visitor.visit_seq(SeqArgsDeser(args, self.options))
       }
-      _ if kids_all_dashes => {
-        visitor.visit_seq(SeqDashChildrenDeser(self.children.unwrap().nodes()))
+      // Properties make it a map, whatever the children are named
+      (false, true) if kids_all_dashes && properties.is_empty() => {
+        visitor.visit_seq(SeqDashChildrenDeser(
+          self.children.unwrap().nodes(),
+          self.options,
+        ))
       }
       (false, true) => self.deserialize_map(visitor),
     }
@@ -167,6 +383,7 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
       children: self.children.map(|x| x.nodes()),
       value: MapDeserVal::None,
       snekify: self.forwarding_to_map_from_struct,
+      options: self.options,
     })
   }
   fn deserialize_struct<V>(
@@ -208,11 +425,11 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
       if !kids_all_dashes {
         return Err(DeError::invalid_type(Unexpected::Other("node invalid as sequence (needs either only args, or children all named `-`)"), &visitor));
       }
-      visitor.visit_seq(SeqDashChildrenDeser(kids.nodes()))
+      visitor.visit_seq(SeqDashChildrenDeser(kids.nodes(), self.options))
     } else {
       let mut args = arguments;
       args.reverse();
-      visitor.visit_seq(SeqArgsDeser(args))
+      visitor.visit_seq(SeqArgsDeser(args, self.options))
     }
   }
 
@@ -220,7 +437,14 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
   where
     V: de::Visitor<'de>,
   {
-    if self.entries.len() == 1 && self.entries[0].value().is_null() {
+    // `a-kid null` is missing, and so is `a-kid` on its own unless it's
+    // a flag
+    let null_arg = matches!(
+      self.entries,
+      [entry] if entry.name().is_none() && entry.value().is_null()
+    );
+    let bare = self.is_bare() && !self.options.flag_nodes();
+    if bare || (null_arg && self.children.is_none()) {
       visitor.visit_none()
     } else {
       visitor.visit_some(self)
@@ -287,12 +511,17 @@ impl<'de> de::Deserializer<'de> for KdlNodeDeser<'de> {
 
   fn deserialize_newtype_struct<V>(
     self,
-    _name: &'static str,
+    name: &'static str,
     visitor: V,
   ) -> Result<V::Value, Self::Error>
   where
     V: de::Visitor<'de>,
   {
+    if name == VALUE_TOKEN {
+      return self.deserialize_value(visitor);
+    } else if name == RAW_NODE_TOKEN {
+      return raw::hand_off(self.node, self.options, visitor);
+    }
     visitor.visit_newtype_struct(self)
   }
 }
@@ -302,6 +531,7 @@ struct MapDeser<'de> {
   properties: Vec<(&'de str, KdlAnnotatedValueWrap<'de>)>,
   children: Option<&'de [KdlNode]>,
   snekify: bool,
+  options: &'de DeOptions,
 
   value: MapDeserVal<'de>,
 }
@@ -338,12 +568,12 @@ impl<'de> de::MapAccess<'de> for MapDeser<'de> {
     } else {
       return Ok(None);
     };
-    let snek = if self.snekify {
-      ToSnekCase::to_snek_case(key)
+    let key = if self.snekify {
+      Cow::Owned(ToSnekCase::to_snek_case(key))
     } else {
-      key.to_owned()
+      Cow::Borrowed(key)
     };
-    seed.deserialize(snek.into_deserializer()).map(Some)
+    seed.deserialize(KeyDeser(key)).map(Some)
   }
 
   fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
@@ -355,16 +585,21 @@ impl<'de> de::MapAccess<'de> for MapDeser<'de> {
         "map visitor requested a value without a key",
       )),
       MapDeserVal::Property(prop) => {
-        seed.deserialize(KdlAnnotatedValueDeser(prop))
+        let res = seed.deserialize(KdlAnnotatedValueDeser(prop, self.options));
+        self.options.locate(prop.value, res)
+      }
+      MapDeserVal::Child(kid) => {
+        let res =
+          seed.deserialize(KdlNodeDeser::with_options(kid, self.options));
+        self.options.locate(kid, res)
       }
-      MapDeserVal::Child(kid) => seed.deserialize(KdlNodeDeser::new(kid)),
     }
   }
 }
 
 /// Sequence deserializer for a struct with only arguments,
 /// Stored backwards for better popping O time
-struct SeqArgsDeser<'de>(Vec<KdlAnnotatedValueWrap<'de>>);
+struct SeqArgsDeser<'de>(Vec<KdlAnnotatedValueWrap<'de>>, &'de DeOptions);
 
 impl<'de> de::SeqAccess<'de> for SeqArgsDeser<'de> {
   type Error = DeError;
@@ -377,7 +612,8 @@ impl<'de> de::SeqAccess<'de> for SeqArgsDeser<'de> {
     T: de::DeserializeSeed<'de>,
   {
     if let Some(head) = self.0.pop() {
-      seed.deserialize(KdlAnnotatedValueDeser(head)).map(Some)
+      let res = seed.deserialize(KdlAnnotatedValueDeser(head, self.1));
+      self.1.locate(head.value, res).map(Some)
     } else {
       Ok(None)
     }
@@ -385,7 +621,7 @@ impl<'de> de::SeqAccess<'de> for SeqArgsDeser<'de> {
 }
 
 /// Sequence deserializer for a struct with only children and all of the nodes are named `-`
-struct SeqDashChildrenDeser<'de>(&'de [KdlNode]);
+struct SeqDashChildrenDeser<'de>(&'de [KdlNode], &'de DeOptions);
 
 impl<'de> de::SeqAccess<'de> for SeqDashChildrenDeser<'de> {
   type Error = DeError;
@@ -399,7 +635,8 @@ impl<'de> de::SeqAccess<'de> for SeqDashChildrenDeser<'de> {
   {
     if let [head, tail @ ..] = self.0 {
       self.0 = tail;
-      seed.deserialize(KdlNodeDeser::new(head)).map(Some)
+      let res = seed.deserialize(KdlNodeDeser::with_options(head, self.1));
+      self.1.locate(head, res).map(Some)
     } else {
       Ok(None)
     }
@@ -0,0 +1,132 @@
+use crate::{annotation::BigInt, DeError};
+
+/// How strict to be when a number has to change type to be deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumericPolicy {
+  /// Integers are cast into floats and `f64`s into `f32`s even if that loses
+  /// precision. Floats can't become integers.
+  #[default]
+  Lenient,
+  /// Conversions have to be exact. Floats can't become integers.
+  Strict,
+  /// Like `Strict`, but floats with no fractional part, like `3.0`, can
+  /// become integers.
+  IntegralFloat,
+}
+
+/// 2^127 and 2^128, the first floats too big for `i128` and `u128`
+const I128_LIMIT: f64 = 170141183460469231731687303715884105728.0;
+const U128_LIMIT: f64 = 340282366920938463463374607431768211456.0;
+
+pub(crate) fn int_to_f64(
+  int: BigInt,
+  policy: NumericPolicy,
+) -> Result<f64, DeError> {
+  let (f, exact) = match int {
+    BigInt::Negative(it) => {
+      let f = it as f64;
+      (f, f as i128 == it)
+    }
+    BigInt::Positive(it) => {
+      let f = it as f64;
+      (f, f < U128_LIMIT && f as u128 == it)
+    }
+  };
+  check_exact(f, exact, int, "f64", policy)
+}
+
+pub(crate) fn int_to_f32(
+  int: BigInt,
+  policy: NumericPolicy,
+) -> Result<f32, DeError> {
+  let (f, exact) = match int {
+    BigInt::Negative(it) => {
+      let f = it as f32;
+      (f, f as i128 == it)
+    }
+    BigInt::Positive(it) => {
+      let f = it as f32;
+      (f, (f as f64) < U128_LIMIT && f as u128 == it)
+    }
+  };
+  check_exact(f, exact, int, "f32", policy)
+}
+
+pub(crate) fn f64_to_f32(
+  f: f64,
+  policy: NumericPolicy,
+) -> Result<f32, DeError> {
+  // For some reason there doesn't seem to be Into or TryInto impls for f64 => f32?
+  let squished = f as f32;
+  let exact = squished as f64 == f || f.is_nan();
+  check_exact(squished, exact, f, "f32", policy)
+}
+
+fn check_exact<T>(
+  out: T,
+  exact: bool,
+  original: impl ToString,
+  target: &'static str,
+  policy: NumericPolicy,
+) -> Result<T, DeError> {
+  if exact || policy == NumericPolicy::Lenient {
+    Ok(out)
+  } else {
+    Err(DeError::LossyNumber {
+      value: original.to_string(),
+      target,
+    })
+  }
+}
+
+/// Fit an integer into a smaller integer type, or say it doesn't fit: with
+/// an `IntSize` when lenient, and a `LossyNumber` otherwise.
+pub(crate) fn narrow<T, I>(
+  int: I,
+  target: &'static str,
+  policy: NumericPolicy,
+) -> Result<T, DeError>
+where
+  I: TryInto<T> + ToString + Copy,
+  DeError: From<I::Error>,
+{
+  int.try_into().map_err(|err| match policy {
+    NumericPolicy::Lenient => DeError::from(err),
+    _ => DeError::LossyNumber {
+      value: int.to_string(),
+      target,
+    },
+  })
+}
+
+/// Turn a float into an integer, if the policy allows it. The caller still
+/// has to check it fits in `target`.
+pub(crate) fn float_to_int(
+  f: f64,
+  target: &'static str,
+  policy: NumericPolicy,
+) -> Result<BigInt, DeError> {
+  if policy != NumericPolicy::IntegralFloat {
+    return Err(DeError::FloatToInt {
+      value: f.to_string(),
+      target,
+    });
+  }
+  if f.fract() != 0.0 || !f.is_finite() {
+    return Err(DeError::NonIntegralFloat {
+      value: f.to_string(),
+      target,
+    });
+  }
+
+  if (-I128_LIMIT..0.0).contains(&f) {
+    Ok(BigInt::Negative(f as i128))
+  } else if (0.0..U128_LIMIT).contains(&f) {
+    Ok(BigInt::Positive(f as u128))
+  } else {
+    Err(DeError::FloatToInt {
+      value: f.to_string(),
+      target,
+    })
+  }
+}
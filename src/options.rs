@@ -0,0 +1,197 @@
+use std::{borrow::Cow, env, fmt, sync::Arc, sync::OnceLock};
+
+use ahash::AHashMap;
+use kdl::{KdlDocument, KdlValue};
+use serde::de::{Error as _, Unexpected};
+use smol_str::SmolStr;
+
+use crate::{interpolate, merge::Provenance, DeError, NumericPolicy, Value};
+
+/// Turns a value with some annotation into what should actually be
+/// deserialized. Return `KdlValue::into()` to hand back another KDL value.
+pub type AnnotationHandler =
+  dyn Fn(&KdlValue) -> Result<Value, DeError> + Send + Sync;
+
+/// Knobs for how knurdy deserializes things.
+///
+/// Pass these to [`crate::deserialize_node_with`] or
+/// [`crate::KdlNodeDeser::with_options`]; they are passed down to every
+/// child node and value.
+#[derive(Clone, Default)]
+pub struct DeOptions {
+  annotations: AHashMap<SmolStr, Arc<AnnotationHandler>>,
+  numeric_policy: NumericPolicy,
+  flag_nodes: bool,
+  interpolation: bool,
+  vars: AHashMap<SmolStr, String>,
+  env_vars: bool,
+  /// Set when deserializing a [`Merged`](crate::merge::Merged)
+  provenance: Option<Arc<Provenance>>,
+}
+
+impl DeOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Handle values annotated with `(name)` using the given function.
+  ///
+  /// Whatever it returns is deserialized in place of the original value.
+  /// These take precedence over the annotations the KDL spec reserves,
+  /// and over enum variants.
+  pub fn with_annotation<F>(
+    mut self,
+    name: impl Into<SmolStr>,
+    handler: F,
+  ) -> Self
+  where
+    F: Fn(&KdlValue) -> Result<Value, DeError> + Send + Sync + 'static,
+  {
+    self.annotations.insert(name.into(), Arc::new(handler));
+    self
+  }
+
+  /// Set how strict to be when numbers need to change type.
+  pub fn with_numeric_policy(mut self, policy: NumericPolicy) -> Self {
+    self.numeric_policy = policy;
+    self
+  }
+
+  /// Let a node with no arguments, properties or children stand in for
+  /// `true` when deserializing a `bool`, so `hidden` means `hidden #true`.
+  /// Put `#[serde(default)]` on the field to make leaving it out mean `false`.
+  ///
+  /// Bare nodes also deserialize as the unit enum variant they're named
+  /// after, either exactly or in kebab-case. A bare node that doesn't name
+  /// one, like a field node, is an error.
+  pub fn with_flag_nodes(mut self, enabled: bool) -> Self {
+    self.flag_nodes = enabled;
+    self
+  }
+
+  /// Replace `${name}` in strings with the variable's value, and `$$` with
+  /// `$`. Naming a variable that isn't defined is an error.
+  pub fn with_interpolation(mut self, enabled: bool) -> Self {
+    self.interpolation = enabled;
+    self
+  }
+
+  /// Define a variable to interpolate.
+  pub fn with_var(
+    mut self,
+    name: impl Into<SmolStr>,
+    value: impl Into<String>,
+  ) -> Self {
+    self.vars.insert(name.into(), value.into());
+    self
+  }
+
+  /// Define the variables in the document's top-level `vars` node, written
+  /// as properties or as children with one argument, like
+  /// `vars { root "assets"; icons "${root}/icons" }`.
+  ///
+  /// Variables can use the ones defined before them, and replace any
+  /// already defined with the same name.
+  pub fn with_document_vars(
+    mut self,
+    doc: &KdlDocument,
+  ) -> Result<Self, DeError> {
+    let Some(vars) = doc.get("vars") else {
+      return Ok(self);
+    };
+    let props = vars
+      .entries()
+      .iter()
+      .filter_map(|it| Some((it.name()?.value(), it.value())));
+    let kids = vars.children().map_or(&[][..], |it| it.nodes());
+    let kids = kids
+      .iter()
+      .filter_map(|it| Some((it.name().value(), it.get(0)?)));
+    for (name, value) in props.chain(kids).collect::<Vec<_>>() {
+      let value = match value {
+        KdlValue::String(it) => interpolate::fill(it, &self)?.into_owned(),
+        KdlValue::Integer(it) => it.to_string(),
+        KdlValue::Float(it) => it.to_string(),
+        KdlValue::Bool(it) => it.to_string(),
+        KdlValue::Null => {
+          return Err(DeError::invalid_type(Unexpected::Unit, &"a variable"))
+        }
+      };
+      self.vars.insert(name.into(), value);
+    }
+    Ok(self)
+  }
+
+  /// Look up variables that aren't defined otherwise in the environment.
+  pub fn with_env_vars(mut self, enabled: bool) -> Self {
+    self.env_vars = enabled;
+    self
+  }
+
+  pub(crate) fn annotation_handler(
+    &self,
+    name: &str,
+  ) -> Option<&AnnotationHandler> {
+    self.annotations.get(name).map(|it| &**it)
+  }
+
+  pub(crate) fn numeric_policy(&self) -> NumericPolicy {
+    self.numeric_policy
+  }
+
+  pub(crate) fn flag_nodes(&self) -> bool {
+    self.flag_nodes
+  }
+
+  pub(crate) fn interpolation(&self) -> bool {
+    self.interpolation
+  }
+
+  pub(crate) fn var(&self, name: &str) -> Option<Cow<'_, str>> {
+    if let Some(value) = self.vars.get(name) {
+      return Some(Cow::Borrowed(value));
+    }
+    self
+      .env_vars
+      .then(|| env::var(name).ok())
+      .flatten()
+      .map(Cow::Owned)
+  }
+
+  pub(crate) fn with_provenance(mut self, provenance: Arc<Provenance>) -> Self {
+    self.provenance = Some(provenance);
+    self
+  }
+
+  /// Say which layer `item` (a node or value) came from, if `res` is an
+  /// error that doesn't say already.
+  pub(crate) fn locate<T, I>(
+    &self,
+    item: &I,
+    res: Result<T, DeError>,
+  ) -> Result<T, DeError> {
+    match (res, &self.provenance) {
+      (Err(err), Some(provenance)) => Err(provenance.locate(item, err)),
+      (res, _) => res,
+    }
+  }
+
+  /// The options used when none are given
+  pub(crate) fn default_ref() -> &'static Self {
+    static DEFAULT: OnceLock<DeOptions> = OnceLock::new();
+    DEFAULT.get_or_init(DeOptions::default)
+  }
+}
+
+impl fmt::Debug for DeOptions {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DeOptions")
+      .field("annotations", &self.annotations.keys().collect::<Vec<_>>())
+      .field("numeric_policy", &self.numeric_policy)
+      .field("flag_nodes", &self.flag_nodes)
+      .field("interpolation", &self.interpolation)
+      .field("vars", &self.vars)
+      .field("env_vars", &self.env_vars)
+      .finish()
+  }
+}
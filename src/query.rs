@@ -0,0 +1,531 @@
+//! [KQL](https://github.com/kdl-org/kdl/blob/main/QUERY-SPEC.md), the KDL
+//! query language, for picking nodes out of a document.
+//!
+//! Supported are `||`, the `>>`, `>`, `++` and `+` combinators, `top()`,
+//! node names and `(type)` annotations, and matchers on `val(n)`,
+//! `prop(name)` (or just `name`), `name()` and `tag()` with `=`, `!=`, `>`,
+//! `>=`, `<`, `<=`, `^=`, `$=` and `*=`.
+
+use std::{cmp::Ordering, collections::HashSet};
+
+use kdl::{KdlDocument, KdlNode, KdlValue};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{DeError, DeOptions, KdlNodeDeser};
+
+/// Something wrong with a query, or with deserializing what it found.
+#[derive(Error, Debug)]
+pub enum QueryError {
+  #[error("invalid query at byte {offset}: {message}")]
+  Syntax { offset: usize, message: &'static str },
+  #[error(transparent)]
+  De(#[from] DeError),
+}
+
+/// Deserialize every node in the document the query selects, in document
+/// order.
+pub fn query<'de, T: Deserialize<'de>>(
+  doc: &'de KdlDocument,
+  query: &str,
+) -> Result<Vec<T>, QueryError> {
+  query_with(doc, query, DeOptions::default_ref())
+}
+
+/// Deserialize every node the query selects with the given options.
+pub fn query_with<'de, T: Deserialize<'de>>(
+  doc: &'de KdlDocument,
+  query: &str,
+  options: &'de DeOptions,
+) -> Result<Vec<T>, QueryError> {
+  let query = Query::parse(query)?;
+  let nodes = query.select(doc);
+  let values = nodes
+    .into_iter()
+    .map(|node| T::deserialize(KdlNodeDeser::with_options(node, options)))
+    .collect::<Result<_, _>>()?;
+  Ok(values)
+}
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+  alternatives: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Selector {
+  first: Filter,
+  rest: Vec<(Combinator, Filter)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+  Descendant,
+  Child,
+  Neighbor,
+  Sibling,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+  Top,
+  Matchers {
+    ty: Option<Option<String>>,
+    name: Option<String>,
+    matchers: Vec<Matcher>,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Matcher {
+  accessor: Accessor,
+  comparison: Option<(Op, KdlValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Accessor {
+  Any,
+  Val(usize),
+  Prop(String),
+  Name,
+  Tag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+  Eq,
+  NotEq,
+  Gt,
+  Gte,
+  Lt,
+  Lte,
+  StartsWith,
+  EndsWith,
+  Contains,
+}
+
+impl Query {
+  pub fn parse(text: &str) -> Result<Self, QueryError> {
+    let mut parser = Parser { text, pos: 0 };
+    let query = parser.query()?;
+    parser.space();
+    if parser.pos != text.len() {
+      return Err(parser.error("expected `||` or a combinator"));
+    }
+    Ok(query)
+  }
+
+  /// The nodes the query selects, in document order.
+  pub fn select<'a>(&self, doc: &'a KdlDocument) -> Vec<&'a KdlNode> {
+    let mut flat = Vec::new();
+    flatten(doc.nodes(), None, &mut flat);
+
+    let mut found = HashSet::new();
+    for selector in &self.alternatives {
+      found.extend(selector.select(&flat));
+    }
+    (0..flat.len())
+      .filter(|idx| found.contains(idx))
+      .map(|idx| flat[idx].node)
+      .collect()
+  }
+}
+
+/// A node along with where it is
+struct Flat<'a> {
+  node: &'a KdlNode,
+  parent: Option<usize>,
+  /// The index of the node after this one with the same parent
+  next_sibling: Option<usize>,
+}
+
+fn flatten<'a>(
+  nodes: &'a [KdlNode],
+  parent: Option<usize>,
+  out: &mut Vec<Flat<'a>>,
+) {
+  let mut previous: Option<usize> = None;
+  for node in nodes {
+    let idx = out.len();
+    if let Some(previous) = previous {
+      out[previous].next_sibling = Some(idx);
+    }
+    out.push(Flat {
+      node,
+      parent,
+      next_sibling: None,
+    });
+    if let Some(kids) = node.children() {
+      flatten(kids.nodes(), Some(idx), out);
+    }
+    previous = Some(idx);
+  }
+}
+
+impl Selector {
+  /// Indices of the selected nodes. `None` stands for the document itself,
+  /// as selected by `top()`.
+  fn select(&self, flat: &[Flat]) -> Vec<usize> {
+    let mut current: Vec<Option<usize>> = match &self.first {
+      Filter::Top => vec![None],
+      filter => (0..flat.len())
+        .filter(|idx| filter.matches(flat[*idx].node))
+        .map(Some)
+        .collect(),
+    };
+
+    for (combinator, filter) in &self.rest {
+      let mut next = Vec::new();
+      for idx in (0..flat.len()).filter(|idx| filter.matches(flat[*idx].node))
+      {
+        let hit = current.iter().any(|it| match combinator {
+          Combinator::Child => flat[idx].parent == *it,
+          Combinator::Descendant => {
+            let mut up = flat[idx].parent;
+            loop {
+              if up == *it {
+                break true;
+              }
+              match up {
+                Some(parent) => up = flat[parent].parent,
+                None => break false,
+              }
+            }
+          }
+          Combinator::Neighbor => {
+            it.and_then(|it| flat[it].next_sibling) == Some(idx)
+          }
+          Combinator::Sibling => {
+            let mut sibling = it.and_then(|it| flat[it].next_sibling);
+            while let Some(it) = sibling {
+              if it == idx {
+                return true;
+              }
+              sibling = flat[it].next_sibling;
+            }
+            false
+          }
+        });
+        if hit {
+          next.push(Some(idx));
+        }
+      }
+      current = next;
+    }
+
+    if current == [None] {
+      // Just `top()`
+      return (0..flat.len()).filter(|it| flat[*it].parent.is_none()).collect();
+    }
+    current.into_iter().flatten().collect()
+  }
+}
+
+impl Filter {
+  fn matches(&self, node: &KdlNode) -> bool {
+    let Filter::Matchers { ty, name, matchers } = self else {
+      return false;
+    };
+    let ty_ok = match ty {
+      None => true,
+      Some(None) => node.ty().is_some(),
+      Some(Some(ty)) => node.ty().map(|it| it.value()) == Some(ty.as_str()),
+    };
+    let name_ok = name.as_ref().is_none_or(|it| node.name().value() == it);
+    ty_ok && name_ok && matchers.iter().all(|it| it.matches(node))
+  }
+}
+
+impl Matcher {
+  fn matches(&self, node: &KdlNode) -> bool {
+    let value = match &self.accessor {
+      Accessor::Any => return true,
+      Accessor::Val(idx) => node.get(*idx).cloned(),
+      Accessor::Prop(key) => node.get(key.as_str()).cloned(),
+      Accessor::Name => Some(node.name().value().into()),
+      Accessor::Tag => node.ty().map(|it| it.value().into()),
+    };
+    let Some(value) = value else {
+      return false;
+    };
+    let Some((op, expected)) = &self.comparison else {
+      return true;
+    };
+
+    let number = |it: &KdlValue| match it {
+      KdlValue::Integer(it) => Some(*it as f64),
+      KdlValue::Float(it) => Some(*it),
+      _ => None,
+    };
+    let strings = value.as_string().zip(expected.as_string());
+    let numbers = number(&value).zip(number(expected));
+    let order = match (&value, expected) {
+      (KdlValue::Integer(a), KdlValue::Integer(b)) => Some(a.cmp(b)),
+      // Only go through floats when there's a float anyways
+      _ => numbers.and_then(|(a, b)| a.partial_cmp(&b)),
+    };
+    let equal = match numbers {
+      Some(_) => order.is_some_and(Ordering::is_eq),
+      None => value == *expected,
+    };
+    match op {
+      Op::Eq => equal,
+      Op::NotEq => !equal,
+      Op::Gt => order.is_some_and(Ordering::is_gt),
+      Op::Gte => order.is_some_and(Ordering::is_ge),
+      Op::Lt => order.is_some_and(Ordering::is_lt),
+      Op::Lte => order.is_some_and(Ordering::is_le),
+      Op::StartsWith => strings.is_some_and(|(a, b)| a.starts_with(b)),
+      Op::EndsWith => strings.is_some_and(|(a, b)| a.ends_with(b)),
+      Op::Contains => strings.is_some_and(|(a, b)| a.contains(b)),
+    }
+  }
+}
+
+struct Parser<'a> {
+  text: &'a str,
+  pos: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn error(&self, message: &'static str) -> QueryError {
+    QueryError::Syntax {
+      offset: self.pos,
+      message,
+    }
+  }
+
+  fn rest(&self) -> &'a str {
+    &self.text[self.pos..]
+  }
+
+  fn space(&mut self) {
+    let rest = self.rest();
+    self.pos += rest.len() - rest.trim_start().len();
+  }
+
+  fn eat(&mut self, token: &str) -> bool {
+    if self.rest().starts_with(token) {
+      self.pos += token.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect(
+    &mut self,
+    token: &str,
+    message: &'static str,
+  ) -> Result<(), QueryError> {
+    self.space();
+    if self.eat(token) {
+      Ok(())
+    } else {
+      Err(self.error(message))
+    }
+  }
+
+  fn query(&mut self) -> Result<Query, QueryError> {
+    let mut alternatives = vec![self.selector()?];
+    loop {
+      self.space();
+      if !self.eat("||") {
+        break;
+      }
+      alternatives.push(self.selector()?);
+    }
+    Ok(Query { alternatives })
+  }
+
+  fn selector(&mut self) -> Result<Selector, QueryError> {
+    self.space();
+    let first = if self.eat("top()") {
+      Filter::Top
+    } else {
+      self.filter()?
+    };
+    let mut rest = Vec::new();
+    loop {
+      self.space();
+      // Longer tokens first
+      let combinator = if self.eat(">>") {
+        Combinator::Descendant
+      } else if self.eat(">") {
+        Combinator::Child
+      } else if self.eat("++") {
+        Combinator::Sibling
+      } else if self.eat("+") {
+        Combinator::Neighbor
+      } else {
+        break;
+      };
+      self.space();
+      rest.push((combinator, self.filter()?));
+    }
+    Ok(Selector { first, rest })
+  }
+
+  fn filter(&mut self) -> Result<Filter, QueryError> {
+    let ty = if self.eat("(") {
+      let ty = self.string_or_ident()?;
+      self.expect(")", "expected `)` after a type annotation")?;
+      Some(ty)
+    } else {
+      None
+    };
+    let name = self.string_or_ident()?;
+    let mut matchers = Vec::new();
+    while self.eat("[") {
+      matchers.push(self.matcher()?);
+    }
+    if ty.is_none() && name.is_none() && matchers.is_empty() {
+      return Err(self.error("expected a node name, type or `[`"));
+    }
+    Ok(Filter::Matchers {
+      ty,
+      name,
+      matchers,
+    })
+  }
+
+  fn matcher(&mut self) -> Result<Matcher, QueryError> {
+    self.space();
+    if self.eat("]") {
+      return Ok(Matcher {
+        accessor: Accessor::Any,
+        comparison: None,
+      });
+    }
+
+    let accessor = if self.eat("val(") {
+      self.space();
+      let start = self.pos;
+      let digits = self.rest().len()
+        - self.rest().trim_start_matches(|c: char| c.is_ascii_digit()).len();
+      self.pos += digits;
+      let idx = match digits {
+        0 => 0,
+        _ => self.text[start..self.pos]
+          .parse()
+          .map_err(|_| self.error("argument index is too big"))?,
+      };
+      self.expect(")", "expected `)` after `val(`")?;
+      Accessor::Val(idx)
+    } else if self.eat("prop(") {
+      self.space();
+      let key = self
+        .string_or_ident()?
+        .ok_or_else(|| self.error("expected a property name"))?;
+      self.expect(")", "expected `)` after `prop(`")?;
+      Accessor::Prop(key)
+    } else if self.eat("name()") {
+      Accessor::Name
+    } else if self.eat("tag()") {
+      Accessor::Tag
+    } else {
+      let key = self
+        .string_or_ident()?
+        .ok_or_else(|| self.error("expected an accessor"))?;
+      Accessor::Prop(key)
+    };
+
+    self.space();
+    let ops = [
+      ("!=", Op::NotEq),
+      (">=", Op::Gte),
+      ("<=", Op::Lte),
+      ("^=", Op::StartsWith),
+      ("$=", Op::EndsWith),
+      ("*=", Op::Contains),
+      ("=", Op::Eq),
+      (">", Op::Gt),
+      ("<", Op::Lt),
+    ];
+    let op = ops.iter().find(|(token, _)| self.eat(token));
+    let comparison = match op {
+      Some((_, op)) => {
+        self.space();
+        Some((*op, self.value()?))
+      }
+      None => None,
+    };
+    self.expect("]", "expected `]`")?;
+    Ok(Matcher {
+      accessor,
+      comparison,
+    })
+  }
+
+  fn value(&mut self) -> Result<KdlValue, QueryError> {
+    // Types on values don't change how they compare
+    if self.eat("(") {
+      self.string_or_ident()?;
+      self.expect(")", "expected `)` after a type annotation")?;
+    }
+    if self.rest().starts_with('"') {
+      return Ok(self.string()?.into());
+    }
+    let word = self.word();
+    let value = match word.trim_start_matches('#') {
+      "" => return Err(self.error("expected a value")),
+      "true" => true.into(),
+      "false" => false.into(),
+      "null" => KdlValue::Null,
+      _ => {
+        let number = word.replace('_', "");
+        if let Ok(it) = number.parse::<i128>() {
+          it.into()
+        } else if let Ok(it) = number.parse::<f64>() {
+          it.into()
+        } else {
+          word.into()
+        }
+      }
+    };
+    Ok(value)
+  }
+
+  fn string_or_ident(&mut self) -> Result<Option<String>, QueryError> {
+    if self.rest().starts_with('"') {
+      return self.string().map(Some);
+    }
+    let word = self.word();
+    Ok((!word.is_empty()).then(|| word.to_owned()))
+  }
+
+  /// A bare identifier, which ends at anything that means something in a
+  /// query
+  fn word(&mut self) -> &'a str {
+    let rest = self.rest();
+    let end = rest
+      .find(|c: char| c.is_whitespace() || "[]()<>=!^$*+|\"".contains(c))
+      .unwrap_or(rest.len());
+    self.pos += end;
+    &rest[..end]
+  }
+
+  fn string(&mut self) -> Result<String, QueryError> {
+    self.pos += 1;
+    let mut out = String::new();
+    let mut chars = self.rest().char_indices();
+    while let Some((idx, c)) = chars.next() {
+      match c {
+        '"' => {
+          self.pos += idx + 1;
+          return Ok(out);
+        }
+        '\\' => match chars.next() {
+          Some((_, 'n')) => out.push('\n'),
+          Some((_, 't')) => out.push('\t'),
+          Some((_, 'r')) => out.push('\r'),
+          Some((_, c)) => out.push(c),
+          None => break,
+        },
+        c => out.push(c),
+      }
+    }
+    Err(self.error("unclosed string"))
+  }
+}
@@ -0,0 +1,156 @@
+use std::{
+  any::TypeId,
+  fmt,
+  marker::PhantomData,
+  mem::{self, ManuallyDrop},
+};
+
+use kdl::KdlNode;
+use serde::de::{self, Deserialize, DeserializeSeed};
+
+use crate::{DeError, DeOptions, KdlNodeDeser};
+
+/// Name of the newtype struct `RawNode` asks for
+pub(crate) const RAW_NODE_TOKEN: &str = "$knurdy::private::RawNode";
+
+/// Give the node to a `RawNodeVisitor`, if that's what `visitor` is.
+///
+/// Any other visitor gets an error, so the node can never end up somewhere
+/// it could outlive its document.
+pub(crate) fn hand_off<'de, V>(
+  node: &'de KdlNode,
+  options: &'de DeOptions,
+  _visitor: V,
+) -> Result<V::Value, DeError>
+where
+  V: de::Visitor<'de>,
+{
+  if type_id_of::<V>() != TypeId::of::<RawNodeVisitor>() {
+    return Err(DeError::RawNodeOutOfPlace);
+  }
+  let raw = ManuallyDrop::new(RawNode::with_options(node, options));
+  // SAFETY: `V` is `RawNodeVisitor`, and its `Value` for this `'de` is
+  // `RawNode<'de>`, exactly the type of `raw`.
+  Ok(unsafe { mem::transmute_copy::<RawNode<'de>, V::Value>(&raw) })
+}
+
+/// `TypeId::of`, for types that might not be `'static`.
+///
+/// Lifetimes are ignored, which is fine for comparing against
+/// `RawNodeVisitor` because it doesn't have any.
+fn type_id_of<T: ?Sized>() -> TypeId {
+  trait NonStaticAny {
+    fn type_id(&self) -> TypeId
+    where
+      Self: 'static;
+  }
+
+  impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+    fn type_id(&self) -> TypeId
+    where
+      Self: 'static,
+    {
+      TypeId::of::<T>()
+    }
+  }
+
+  let phantom = PhantomData::<T>;
+  let any: &dyn NonStaticAny = &phantom;
+  // SAFETY: lifetimes are gone by runtime, so `TypeId::of` can't depend on
+  // them, and nothing else is done with the reference.
+  let any = unsafe {
+    mem::transmute::<&dyn NonStaticAny, &(dyn NonStaticAny + 'static)>(any)
+  };
+  any.type_id()
+}
+
+/// A node whose deserialization has been put off until later.
+///
+/// Put this in a struct to grab a child node as-is, then call
+/// [`RawNode::deserialize`] once you know what type it should be.
+/// This is knurdy's version of `serde_json`'s `RawValue`.
+///
+/// This only works when deserializing from a `KdlNodeDeser`; values
+/// (arguments and properties) can't be raw nodes. Serde buffers the input
+/// for `#[serde(flatten)]` and for untagged and internally tagged enums, so
+/// a `RawNode` can't be inside any of those either, and you'll get a
+/// [`DeError::RawNodeOutOfPlace`].
+///
+/// Note that `RawNode::deserialize` is the inherent method; to deserialize
+/// a `RawNode` itself by hand, write `<RawNode as Deserialize>::deserialize`.
+/// The options it was deserialized with are kept around for later.
+#[derive(Debug, Clone, Copy)]
+pub struct RawNode<'de> {
+  node: &'de KdlNode,
+  options: &'de DeOptions,
+}
+
+impl<'de> RawNode<'de> {
+  pub fn new(node: &'de KdlNode) -> Self {
+    Self::with_options(node, DeOptions::default_ref())
+  }
+
+  pub fn with_options(node: &'de KdlNode, options: &'de DeOptions) -> Self {
+    Self { node, options }
+  }
+
+  /// The node that was captured
+  pub fn node(&self) -> &'de KdlNode {
+    self.node
+  }
+
+  pub fn name(&self) -> &'de str {
+    self.node.name().value()
+  }
+
+  /// Get a deserializer for the captured node.
+  pub fn deserializer(&self) -> KdlNodeDeser<'de> {
+    KdlNodeDeser::with_options(self.node, self.options)
+  }
+
+  /// Deserialize the captured node.
+  pub fn deserialize<T: Deserialize<'de>>(&self) -> Result<T, DeError> {
+    T::deserialize(self.deserializer())
+  }
+
+  /// Deserialize the captured node with a seed.
+  pub fn deserialize_seed<S: DeserializeSeed<'de>>(
+    &self,
+    seed: S,
+  ) -> Result<S::Value, DeError> {
+    seed.deserialize(self.deserializer())
+  }
+}
+
+impl<'de> From<&'de KdlNode> for RawNode<'de> {
+  fn from(node: &'de KdlNode) -> Self {
+    Self::new(node)
+  }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RawNode<'a> {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: de::Deserializer<'de>,
+  {
+    deserializer.deserialize_newtype_struct(RAW_NODE_TOKEN, RawNodeVisitor)
+  }
+}
+
+struct RawNodeVisitor;
+
+impl<'de> de::Visitor<'de> for RawNodeVisitor {
+  type Value = RawNode<'de>;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a KDL node straight from a `KdlNodeDeser`")
+  }
+
+  /// What serde's buffered input hands over instead of the node
+  fn visit_newtype_struct<D>(self, _: D) -> Result<Self::Value, D::Error>
+  where
+    D: de::Deserializer<'de>,
+  {
+    Err(de::Error::custom(DeError::RawNodeOutOfPlace))
+  }
+}
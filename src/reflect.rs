@@ -0,0 +1,798 @@
+//! Finding out what shape a type's data has, by deserializing it from a
+//! deserializer that makes up values and writes down what was asked for.
+//!
+//! Every enum variant is visited, over as many passes as it takes. Options,
+//! sequences and maps inside a type that contains itself are cut short so
+//! this finishes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::de::{
+  self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor,
+};
+
+use crate::DeError;
+
+/// The shape of some data, in serde's data model.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) enum Shape {
+  /// Never reached, because tracing cut it short
+  #[default]
+  Unknown,
+  /// Asked for with `deserialize_any`, so it could be anything
+  Any,
+  Unit,
+  Bool,
+  I8,
+  I16,
+  I32,
+  I64,
+  I128,
+  U8,
+  U16,
+  U32,
+  U64,
+  U128,
+  F32,
+  F64,
+  Char,
+  Str,
+  Bytes,
+  Option(Box<Shape>),
+  Seq(Box<Shape>),
+  Map(Box<Shape>, Box<Shape>),
+  Tuple(Vec<Shape>),
+  /// A struct or enum in the registry
+  Named(&'static str),
+}
+
+/// A named struct or enum.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Container {
+  Unit,
+  Newtype(Shape),
+  Tuple(Vec<Shape>),
+  Struct(Vec<(&'static str, Shape)>),
+  Enum(Vec<(&'static str, Variant)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) enum Variant {
+  #[default]
+  Unknown,
+  Unit,
+  Newtype(Shape),
+  Tuple(Vec<Shape>),
+  Struct(Vec<(&'static str, Shape)>),
+}
+
+pub(crate) type Registry = BTreeMap<&'static str, Container>;
+
+/// A shape that knurdy reads from a single value, like a property.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Literal {
+  /// One of the scalar shapes, with `Option`s and newtypes unwrapped
+  Scalar(Shape),
+  /// An enum whose variants are all units (`None`), or newtypes holding a
+  /// literal in an annotation
+  Enum(Vec<(&'static str, Option<Literal>)>),
+}
+
+/// How `shape` is written as a single value, or `None` if it can't be.
+pub(crate) fn literal(shape: &Shape, registry: &Registry) -> Option<Literal> {
+  literal_inner(shape, registry, &mut Vec::new())
+}
+
+fn literal_inner(
+  shape: &Shape,
+  registry: &Registry,
+  seen: &mut Vec<&'static str>,
+) -> Option<Literal> {
+  match shape {
+    Shape::Unit
+    | Shape::Bool
+    | Shape::I8
+    | Shape::I16
+    | Shape::I32
+    | Shape::I64
+    | Shape::I128
+    | Shape::U8
+    | Shape::U16
+    | Shape::U32
+    | Shape::U64
+    | Shape::U128
+    | Shape::F32
+    | Shape::F64
+    | Shape::Char
+    | Shape::Str => Some(Literal::Scalar(shape.clone())),
+    Shape::Option(inner) => literal_inner(inner, registry, seen),
+    Shape::Named(name) => {
+      if seen.contains(name) {
+        return None;
+      }
+      seen.push(name);
+      match registry.get(name)? {
+        Container::Newtype(inner) => literal_inner(inner, registry, seen),
+        Container::Enum(variants) => {
+          let variants = variants
+            .iter()
+            .map(|(name, variant)| match variant {
+              Variant::Unit => Some((*name, None)),
+              Variant::Newtype(inner) => {
+                Some((*name, Some(literal_inner(inner, registry, seen)?)))
+              }
+              _ => None,
+            })
+            .collect::<Option<_>>()?;
+          Some(Literal::Enum(variants))
+        }
+        _ => None,
+      }
+    }
+    _ => None,
+  }
+}
+
+impl Shape {
+  /// Fill in whatever we didn't know yet
+  fn merge(&mut self, other: Shape) {
+    match (self, other) {
+      (_, Shape::Unknown) => {}
+      (this @ Shape::Unknown, other) => *this = other,
+      (Shape::Option(this), Shape::Option(other))
+      | (Shape::Seq(this), Shape::Seq(other)) => this.merge(*other),
+      (Shape::Map(key, value), Shape::Map(other_key, other_value)) => {
+        key.merge(*other_key);
+        value.merge(*other_value);
+      }
+      (Shape::Tuple(this), Shape::Tuple(other)) => merge_all(this, other),
+      _ => {}
+    }
+  }
+}
+
+fn merge_all(this: &mut [Shape], other: Vec<Shape>) {
+  for (this, other) in this.iter_mut().zip(other) {
+    this.merge(other);
+  }
+}
+
+fn merge_fields(
+  this: &mut [(&'static str, Shape)],
+  other: Vec<(&'static str, Shape)>,
+) {
+  for ((_, this), (_, other)) in this.iter_mut().zip(other) {
+    this.merge(other);
+  }
+}
+
+impl Container {
+  fn merge(&mut self, other: Container) {
+    match (self, other) {
+      (Container::Newtype(this), Container::Newtype(other)) => {
+        this.merge(other)
+      }
+      (Container::Tuple(this), Container::Tuple(other)) => {
+        merge_all(this, other)
+      }
+      (Container::Struct(this), Container::Struct(other)) => {
+        merge_fields(this, other)
+      }
+      _ => {}
+    }
+  }
+}
+
+impl Variant {
+  fn merge(&mut self, other: Variant) {
+    match (self, other) {
+      (_, Variant::Unknown) => {}
+      (this @ Variant::Unknown, other) => *this = other,
+      (Variant::Newtype(this), Variant::Newtype(other)) => this.merge(other),
+      (Variant::Tuple(this), Variant::Tuple(other)) => merge_all(this, other),
+      (Variant::Struct(this), Variant::Struct(other)) => {
+        merge_fields(this, other)
+      }
+      _ => {}
+    }
+  }
+}
+
+/// Trace `T`, returning its shape and every struct and enum inside it.
+pub(crate) fn trace<T: DeserializeOwned>(
+) -> Result<(Shape, Registry), DeError> {
+  let mut tracer = Tracer::default();
+  let mut shape = Shape::Unknown;
+  loop {
+    tracer.progress = false;
+    T::deserialize(TraceDeser {
+      tracer: &mut tracer,
+      shape: &mut shape,
+    })?;
+    // Stop once every variant has been seen, or if the rest can't be
+    // reached at all
+    if !tracer.progress || tracer.unexplored.values().all(|&it| it == 0) {
+      return Ok((shape, tracer.registry));
+    }
+  }
+}
+
+/// Which struct fields can be left out, because they have a default or are
+/// `Option`s, as `(struct, field)` pairs.
+///
+/// This deserializes `T` again for every field of every struct in the
+/// registry, handing the struct a map without that field.
+pub(crate) fn defaults<T: DeserializeOwned>(
+  registry: &Registry,
+) -> BTreeSet<(&'static str, &'static str)> {
+  let mut out = BTreeSet::new();
+  for (&name, container) in registry {
+    let Container::Struct(fields) = container else {
+      continue;
+    };
+    for &(field, _) in fields {
+      let mut tracer = Tracer {
+        probe: Some((name, field)),
+        ..Tracer::default()
+      };
+      // It might take a few passes to get to an enum variant holding it
+      loop {
+        tracer.progress = false;
+        let res = T::deserialize(TraceDeser {
+          tracer: &mut tracer,
+          shape: &mut Shape::Unknown,
+        });
+        if tracer.probed {
+          if res.is_ok() {
+            out.insert((name, field));
+          }
+          break;
+        }
+        if res.is_err() || !tracer.progress {
+          break;
+        }
+      }
+    }
+  }
+  out
+}
+
+#[derive(Debug, Default)]
+struct Tracer {
+  registry: Registry,
+  /// Containers we're inside of
+  stack: Vec<&'static str>,
+  /// How many of the containers on the stack are in there twice
+  recursing: usize,
+  /// How many variants of each enum haven't been visited yet
+  unexplored: BTreeMap<&'static str, usize>,
+  /// Whether this pass visited a new variant
+  progress: bool,
+  /// A struct field to leave out, to see if the struct minds
+  probe: Option<(&'static str, &'static str)>,
+  /// Whether the probed struct has been reached
+  probed: bool,
+}
+
+impl Tracer {
+  fn enter(&mut self, name: &'static str) {
+    if self.stack.contains(&name) {
+      self.recursing += 1;
+    }
+    self.stack.push(name);
+  }
+
+  fn exit(&mut self) {
+    let name = self.stack.pop().unwrap();
+    if self.stack.contains(&name) {
+      self.recursing -= 1;
+    }
+  }
+
+  fn record(&mut self, name: &'static str, container: Container) {
+    match self.registry.get_mut(name) {
+      Some(it) => it.merge(container),
+      None => {
+        self.registry.insert(name, container);
+      }
+    }
+  }
+
+  /// Which variant to visit next
+  fn pick_variant(
+    &mut self,
+    name: &'static str,
+    variants: &'static [&'static str],
+  ) -> usize {
+    let Some(Container::Enum(known)) = self.registry.get(name) else {
+      let known = variants
+        .iter()
+        .map(|&variant| (variant, Variant::Unknown))
+        .collect();
+      self.registry.insert(name, Container::Enum(known));
+      self.unexplored.insert(name, variants.len());
+      return self.pick_variant(name, variants);
+    };
+
+    let unexplored = known
+      .iter()
+      .position(|(_, variant)| *variant == Variant::Unknown);
+    match unexplored {
+      // Heading back into ourselves might never end, so go with something
+      // that won't
+      _ if self.recursing > 0 || self.stack.contains(&name) => known
+        .iter()
+        .position(|(_, variant)| *variant == Variant::Unit)
+        .unwrap_or(0),
+      Some(idx) => idx,
+      None => 0,
+    }
+  }
+
+  fn record_variant(&mut self, name: &'static str, idx: usize, it: Variant) {
+    if let Some(Container::Enum(known)) = self.registry.get_mut(name) {
+      let variant = &mut known[idx].1;
+      if *variant == Variant::Unknown && it != Variant::Unknown {
+        self.progress = true;
+        *self.unexplored.get_mut(name).unwrap() -= 1;
+      }
+      variant.merge(it);
+    }
+  }
+}
+
+struct TraceDeser<'t> {
+  tracer: &'t mut Tracer,
+  shape: &'t mut Shape,
+}
+
+impl<'t> TraceDeser<'t> {
+  fn new(tracer: &'t mut Tracer, shape: &'t mut Shape) -> Self {
+    Self { tracer, shape }
+  }
+}
+
+/// Make up a zero
+macro_rules! trace_scalar {
+  (@ $ty:ident $shape:ident $value:expr) => {
+    paste::paste! {
+      fn [< deserialize_ $ty >]<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+      where
+        V: Visitor<'de>,
+      {
+        self.shape.merge(Shape::$shape);
+        visitor.[< visit_ $ty >]($value)
+      }
+    }
+  };
+  ( $($ty:ident $shape:ident $value:expr;)* ) => {
+    $(
+      trace_scalar!(@ $ty $shape $value);
+    )*
+  };
+}
+
+impl<'de, 't> de::Deserializer<'de> for TraceDeser<'t> {
+  type Error = DeError;
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.shape.merge(Shape::Any);
+    visitor.visit_unit()
+  }
+
+  trace_scalar! {
+    bool Bool false;
+    i8 I8 0; i16 I16 0; i32 I32 0; i64 I64 0; i128 I128 0;
+    u8 U8 0; u16 U16 0; u32 U32 0; u64 U64 0; u128 U128 0;
+    f32 F32 0.0; f64 F64 0.0;
+    char Char '\0';
+  }
+
+  fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.shape.merge(Shape::Str);
+    visitor.visit_str("")
+  }
+  fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_str(visitor)
+  }
+
+  fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.shape.merge(Shape::Bytes);
+    visitor.visit_bytes(&[])
+  }
+  fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_bytes(visitor)
+  }
+
+  fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    if self.tracer.recursing > 0 {
+      self.shape.merge(Shape::Option(Box::default()));
+      return visitor.visit_none();
+    }
+    let mut inner = Shape::Unknown;
+    let out = visitor.visit_some(TraceDeser::new(self.tracer, &mut inner));
+    self.shape.merge(Shape::Option(Box::new(inner)));
+    out
+  }
+
+  fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.shape.merge(Shape::Unit);
+    visitor.visit_unit()
+  }
+  fn deserialize_unit_struct<V>(
+    self,
+    name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.tracer.record(name, Container::Unit);
+    self.shape.merge(Shape::Named(name));
+    visitor.visit_unit()
+  }
+
+  fn deserialize_newtype_struct<V>(
+    self,
+    name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    // knurdy's own magic types take anything
+    if name.starts_with("$knurdy::private::") {
+      self.shape.merge(Shape::Any);
+      return visitor.visit_newtype_struct(TraceDeser::new(
+        self.tracer,
+        &mut Shape::Unknown,
+      ));
+    }
+    let mut inner = Shape::Unknown;
+    self.tracer.enter(name);
+    let out =
+      visitor.visit_newtype_struct(TraceDeser::new(self.tracer, &mut inner));
+    self.tracer.exit();
+    self.tracer.record(name, Container::Newtype(inner));
+    self.shape.merge(Shape::Named(name));
+    out
+  }
+
+  fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let mut items = vec![Shape::Unknown];
+    let len = if self.tracer.recursing > 0 { 0 } else { 1 };
+    let out = visitor.visit_seq(TraceSeq {
+      tracer: self.tracer,
+      shapes: items[..len].iter_mut(),
+    });
+    self.shape.merge(Shape::Seq(Box::new(items.pop().unwrap())));
+    out
+  }
+
+  fn deserialize_tuple<V>(
+    self,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let mut items = vec![Shape::Unknown; len];
+    let out = visitor.visit_seq(TraceSeq {
+      tracer: self.tracer,
+      shapes: items.iter_mut(),
+    });
+    self.shape.merge(Shape::Tuple(items));
+    out
+  }
+  fn deserialize_tuple_struct<V>(
+    self,
+    name: &'static str,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let mut items = vec![Shape::Unknown; len];
+    self.tracer.enter(name);
+    let out = visitor.visit_seq(TraceSeq {
+      tracer: self.tracer,
+      shapes: items.iter_mut(),
+    });
+    self.tracer.exit();
+    self.tracer.record(name, Container::Tuple(items));
+    self.shape.merge(Shape::Named(name));
+    out
+  }
+
+  fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let mut key = Shape::Unknown;
+    let mut value = Shape::Unknown;
+    let left = if self.tracer.recursing > 0 { 0 } else { 1 };
+    let out = visitor.visit_map(TraceMap {
+      tracer: self.tracer,
+      key: &mut key,
+      value: &mut value,
+      left,
+    });
+    self.shape.merge(Shape::Map(Box::new(key), Box::new(value)));
+    out
+  }
+
+  fn deserialize_struct<V>(
+    self,
+    name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    if let Some((probe, skip)) = self.tracer.probe {
+      if probe == name && !self.tracer.probed {
+        self.tracer.probed = true;
+        self.shape.merge(Shape::Named(name));
+        return visitor.visit_map(TraceFields {
+          tracer: self.tracer,
+          fields: fields.iter(),
+          skip,
+        });
+      }
+    }
+
+    // Derived structs take their fields in order as a sequence
+    let mut shapes = vec![Shape::Unknown; fields.len()];
+    self.tracer.enter(name);
+    let out = visitor.visit_seq(TraceSeq {
+      tracer: self.tracer,
+      shapes: shapes.iter_mut(),
+    });
+    self.tracer.exit();
+    let fields = fields.iter().copied().zip(shapes).collect();
+    self.tracer.record(name, Container::Struct(fields));
+    self.shape.merge(Shape::Named(name));
+    out
+  }
+
+  fn deserialize_enum<V>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let idx = self.tracer.pick_variant(name, variants);
+    self.tracer.enter(name);
+    let out = visitor.visit_enum(TraceEnum {
+      tracer: self.tracer,
+      name,
+      variants,
+      idx,
+    });
+    self.tracer.exit();
+    self.shape.merge(Shape::Named(name));
+    out
+  }
+
+  fn deserialize_identifier<V>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_str(visitor)
+  }
+
+  fn deserialize_ignored_any<V>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_unit()
+  }
+}
+
+struct TraceSeq<'t, 's> {
+  tracer: &'t mut Tracer,
+  shapes: std::slice::IterMut<'s, Shape>,
+}
+
+impl<'de, 't, 's> de::SeqAccess<'de> for TraceSeq<'t, 's> {
+  type Error = DeError;
+
+  fn next_element_seed<T>(
+    &mut self,
+    seed: T,
+  ) -> Result<Option<T::Value>, Self::Error>
+  where
+    T: DeserializeSeed<'de>,
+  {
+    match self.shapes.next() {
+      Some(shape) => {
+        seed.deserialize(TraceDeser::new(self.tracer, shape)).map(Some)
+      }
+      None => Ok(None),
+    }
+  }
+}
+
+struct TraceMap<'t> {
+  tracer: &'t mut Tracer,
+  key: &'t mut Shape,
+  value: &'t mut Shape,
+  left: usize,
+}
+
+impl<'de, 't> de::MapAccess<'de> for TraceMap<'t> {
+  type Error = DeError;
+
+  fn next_key_seed<K>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>, Self::Error>
+  where
+    K: DeserializeSeed<'de>,
+  {
+    if self.left == 0 {
+      return Ok(None);
+    }
+    self.left -= 1;
+    seed
+      .deserialize(TraceDeser::new(self.tracer, self.key))
+      .map(Some)
+  }
+
+  fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+  where
+    V: DeserializeSeed<'de>,
+  {
+    seed.deserialize(TraceDeser::new(self.tracer, self.value))
+  }
+}
+
+/// A struct's fields by name, except for one
+struct TraceFields<'t> {
+  tracer: &'t mut Tracer,
+  fields: std::slice::Iter<'static, &'static str>,
+  skip: &'static str,
+}
+
+impl<'de, 't> de::MapAccess<'de> for TraceFields<'t> {
+  type Error = DeError;
+
+  fn next_key_seed<K>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>, Self::Error>
+  where
+    K: DeserializeSeed<'de>,
+  {
+    let skip = self.skip;
+    match self.fields.find(|&&it| it != skip) {
+      Some(&field) => {
+        let field: de::value::StrDeserializer<DeError> =
+          field.into_deserializer();
+        seed.deserialize(field).map(Some)
+      }
+      None => Ok(None),
+    }
+  }
+
+  fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+  where
+    V: DeserializeSeed<'de>,
+  {
+    seed.deserialize(TraceDeser::new(self.tracer, &mut Shape::Unknown))
+  }
+}
+
+struct TraceEnum<'t> {
+  tracer: &'t mut Tracer,
+  name: &'static str,
+  variants: &'static [&'static str],
+  idx: usize,
+}
+
+impl<'de, 't> de::EnumAccess<'de> for TraceEnum<'t> {
+  type Error = DeError;
+  type Variant = Self;
+
+  fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Self::Error>
+  where
+    V: DeserializeSeed<'de>,
+  {
+    let variant: de::value::StrDeserializer<DeError> =
+      self.variants[self.idx].into_deserializer();
+    Ok((seed.deserialize(variant)?, self))
+  }
+}
+
+impl<'de, 't> de::VariantAccess<'de> for TraceEnum<'t> {
+  type Error = DeError;
+
+  fn unit_variant(self) -> Result<(), Self::Error> {
+    self.tracer.record_variant(self.name, self.idx, Variant::Unit);
+    Ok(())
+  }
+
+  fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+  where
+    T: DeserializeSeed<'de>,
+  {
+    let mut inner = Shape::Unknown;
+    let out = seed.deserialize(TraceDeser::new(self.tracer, &mut inner));
+    let variant = Variant::Newtype(inner);
+    self.tracer.record_variant(self.name, self.idx, variant);
+    out
+  }
+
+  fn tuple_variant<V>(
+    self,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let mut items = vec![Shape::Unknown; len];
+    let out = visitor.visit_seq(TraceSeq {
+      tracer: self.tracer,
+      shapes: items.iter_mut(),
+    });
+    let variant = Variant::Tuple(items);
+    self.tracer.record_variant(self.name, self.idx, variant);
+    out
+  }
+
+  fn struct_variant<V>(
+    self,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let mut shapes = vec![Shape::Unknown; fields.len()];
+    let out = visitor.visit_seq(TraceSeq {
+      tracer: self.tracer,
+      shapes: shapes.iter_mut(),
+    });
+    let fields = fields.iter().copied().zip(shapes).collect();
+    let variant = Variant::Struct(fields);
+    self.tracer.record_variant(self.name, self.idx, variant);
+    out
+  }
+}
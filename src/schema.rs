@@ -0,0 +1,648 @@
+//! Checking documents against a
+//! [KDL Schema](https://github.com/kdl-org/kdl/blob/main/SCHEMA-SPEC.md)
+//! before deserializing them, so mistakes come out all at once and with
+//! the path to the node they're on.
+//!
+//! This understands `node` rules with `min`, `max`, `value`, `prop`,
+//! `children` and `other-props-allowed`, along with `other-nodes-allowed`,
+//! `definitions` and `ref="[id=\"...\"]"`. Values can be checked with
+//! `type`, `enum`, `min-length`, `max-length`, `>`, `>=`, `<`, `<=`, `%`,
+//! `tag` and (with the `regex` feature) `pattern`. Everything else, like
+//! `info` and `format`, is ignored.
+//!
+//! As in the spec, properties that aren't in the schema aren't allowed
+//! unless `other-props-allowed` is set. Nodes without a `value` rule can
+//! have any arguments, and nodes without a `children` rule can have any
+//! children.
+
+use std::{collections::HashMap, fmt::Display};
+
+use kdl::{KdlDocument, KdlEntry, KdlError, KdlNode, KdlValue};
+use thiserror::Error;
+
+use crate::{parse_document, KdlVersion};
+
+mod generate;
+
+pub use generate::generate;
+#[cfg(feature = "serde_json")]
+pub use generate::json_schema;
+
+/// A loaded schema.
+#[derive(Debug, Clone)]
+pub struct Schema {
+  root: ChildRules,
+  definitions: HashMap<String, NodeRule>,
+}
+
+/// Something wrong with a schema document itself.
+#[derive(Error, Debug)]
+pub enum SchemaError {
+  #[error(transparent)]
+  Parse(#[from] KdlError),
+  #[error("the schema has no `document` node")]
+  NoDocument,
+  #[error("{path}: {message}")]
+  Invalid { path: String, message: String },
+}
+
+/// One way a document doesn't match a schema.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{path}: {message}")]
+pub struct Violation {
+  /// Where the problem is, like `/package/dependencies/serde`.
+  ///
+  /// When several siblings share a name, they're told apart with their
+  /// index among those, like `/list/item[2]`.
+  pub path: String,
+  pub message: String,
+}
+
+impl Schema {
+  /// Load a schema from its `document` node.
+  pub fn new(schema: &KdlDocument) -> Result<Self, SchemaError> {
+    let document = schema
+      .nodes()
+      .iter()
+      .find(|node| node.name().value() == "document")
+      .ok_or(SchemaError::NoDocument)?;
+
+    let mut loader = Loader::default();
+    let root = loader.children(document, "/document")?;
+    for (path, id) in loader.refs {
+      if !loader.definitions.contains_key(&id) {
+        return Err(invalid(&path, format!("no node has the id `{}`", id)));
+      }
+    }
+    Ok(Self {
+      root,
+      definitions: loader.definitions,
+    })
+  }
+
+  /// Parse and load a schema.
+  pub fn parse(text: &str) -> Result<Self, SchemaError> {
+    Self::new(&parse_document(text, KdlVersion::Detect)?)
+  }
+
+  /// Check a document, returning everything wrong with it.
+  pub fn validate(&self, doc: &KdlDocument) -> Result<(), Vec<Violation>> {
+    let mut validator = Validator {
+      schema: self,
+      violations: Vec::new(),
+    };
+    validator.nodes(&self.root, doc.nodes(), "/");
+    if validator.violations.is_empty() {
+      Ok(())
+    } else {
+      Err(validator.violations)
+    }
+  }
+
+  /// Follow `ref`s to the rule that says what the node looks like
+  fn body<'s>(&'s self, mut rule: &'s NodeRule) -> &'s NodeRule {
+    // Bounded, in case definitions refer to each other in a loop
+    for _ in 0..=self.definitions.len() {
+      match &rule.reference {
+        Some(id) => rule = &self.definitions[id],
+        None => break,
+      }
+    }
+    rule
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ChildRules {
+  nodes: Vec<NodeRule>,
+  other_nodes_allowed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct NodeRule {
+  /// `None` matches any node no other rule does
+  name: Option<String>,
+  min: Option<usize>,
+  max: Option<usize>,
+  reference: Option<String>,
+  values: Option<ValueRule>,
+  props: Vec<PropRule>,
+  other_props_allowed: bool,
+  children: Option<ChildRules>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ValueRule {
+  min: Option<usize>,
+  max: Option<usize>,
+  checks: Checks,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PropRule {
+  /// `None` matches any property no other rule does
+  key: Option<String>,
+  required: bool,
+  checks: Checks,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Checks {
+  ty: Option<String>,
+  one_of: Option<Vec<KdlValue>>,
+  /// Checks on the annotation, as a string
+  tag: Option<Box<Checks>>,
+  #[cfg(feature = "regex")]
+  pattern: Option<regex::Regex>,
+  min_length: Option<usize>,
+  max_length: Option<usize>,
+  bounds: Vec<(Bound, f64)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Bound {
+  Gt,
+  Ge,
+  Lt,
+  Le,
+  MultipleOf,
+}
+
+fn invalid(path: &str, message: impl Display) -> SchemaError {
+  SchemaError::Invalid {
+    path: path.to_owned(),
+    message: message.to_string(),
+  }
+}
+
+/// Nodes along with their paths
+pub(crate) fn with_paths<'a>(
+  nodes: &'a [KdlNode],
+  path: &'a str,
+) -> impl Iterator<Item = (&'a KdlNode, String)> + 'a {
+  nodes.iter().enumerate().map(move |(idx, node)| {
+    let name = node.name().value();
+    let same = |other: &&KdlNode| other.name().value() == name;
+    let sep = if path.ends_with('/') { "" } else { "/" };
+    if nodes.iter().filter(same).count() > 1 {
+      let nth = nodes[..idx].iter().filter(same).count();
+      (node, format!("{}{}{}[{}]", path, sep, name, nth))
+    } else {
+      (node, format!("{}{}{}", path, sep, name))
+    }
+  })
+}
+
+fn kids(node: &KdlNode) -> &[KdlNode] {
+  node.children().map_or(&[][..], |kids| kids.nodes())
+}
+
+fn arg_entries(node: &KdlNode) -> impl Iterator<Item = &KdlEntry> {
+  node.entries().iter().filter(|entry| entry.name().is_none())
+}
+
+fn args(node: &KdlNode) -> impl Iterator<Item = &KdlValue> {
+  arg_entries(node).map(KdlEntry::value)
+}
+
+fn prop_entry<'a>(node: &'a KdlNode, key: &str) -> Option<&'a KdlEntry> {
+  // Later properties override earlier ones
+  node
+    .entries()
+    .iter()
+    .rev()
+    .find(|entry| entry.name().map(|name| name.value()) == Some(key))
+}
+
+fn prop<'a>(node: &'a KdlNode, key: &str) -> Option<&'a KdlValue> {
+  prop_entry(node, key).map(KdlEntry::value)
+}
+
+fn arg<'a>(node: &'a KdlNode, path: &str) -> Result<&'a KdlValue, SchemaError> {
+  args(node)
+    .next()
+    .ok_or_else(|| invalid(path, "expected an argument"))
+}
+
+fn string(value: &KdlValue, path: &str) -> Result<String, SchemaError> {
+  match value {
+    KdlValue::String(s) => Ok(s.clone()),
+    _ => Err(invalid(path, "expected a string")),
+  }
+}
+
+fn count(value: &KdlValue, path: &str) -> Result<usize, SchemaError> {
+  match value {
+    KdlValue::Integer(it) => usize::try_from(*it).ok(),
+    _ => None,
+  }
+  .ok_or_else(|| invalid(path, "expected a non-negative integer"))
+}
+
+/// Quote strings, so they don't look like numbers or keywords
+fn show(value: &KdlValue) -> String {
+  match value {
+    KdlValue::String(s) => format!("{:?}", s),
+    _ => value.to_string(),
+  }
+}
+
+fn number(value: &KdlValue) -> Option<f64> {
+  match value {
+    KdlValue::Integer(it) => Some(*it as f64),
+    KdlValue::Float(f) => Some(*f),
+    _ => None,
+  }
+}
+
+#[derive(Default)]
+struct Loader {
+  definitions: HashMap<String, NodeRule>,
+  /// Checked once everything is loaded
+  refs: Vec<(String, String)>,
+}
+
+impl Loader {
+  fn children(
+    &mut self,
+    node: &KdlNode,
+    path: &str,
+  ) -> Result<ChildRules, SchemaError> {
+    let mut rules = ChildRules::default();
+    for (kid, path) in with_paths(kids(node), path) {
+      match kid.name().value() {
+        "node" => rules.nodes.push(self.node(kid, &path)?),
+        "other-nodes-allowed" => {
+          rules.other_nodes_allowed = arg(kid, &path)?.as_bool() == Some(true)
+        }
+        "definitions" => {
+          for (def, path) in with_paths(kids(kid), &path) {
+            if def.name().value() == "node" {
+              self.node(def, &path)?;
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+    Ok(rules)
+  }
+
+  fn node(
+    &mut self,
+    node: &KdlNode,
+    path: &str,
+  ) -> Result<NodeRule, SchemaError> {
+    let mut rule = NodeRule {
+      name: args(node).next().map(|it| string(it, path)).transpose()?,
+      ..Default::default()
+    };
+    if let Some(reference) = prop(node, "ref") {
+      let id = ref_id(&string(reference, path)?)
+        .ok_or_else(|| invalid(path, "refs must look like `[id=\"...\"]`"))?;
+      self.refs.push((path.to_owned(), id.clone()));
+      rule.reference = Some(id);
+    }
+
+    for (kid, path) in with_paths(kids(node), path) {
+      match kid.name().value() {
+        "min" => rule.min = Some(count(arg(kid, &path)?, &path)?),
+        "max" => rule.max = Some(count(arg(kid, &path)?, &path)?),
+        "value" => rule.values = Some(self.value(kid, &path)?),
+        "prop" => rule.props.push(self.prop(kid, &path)?),
+        "other-props-allowed" => {
+          rule.other_props_allowed = arg(kid, &path)?.as_bool() == Some(true)
+        }
+        "children" => rule.children = Some(self.children(kid, &path)?),
+        _ => {}
+      }
+    }
+
+    if let Some(id) = prop(node, "id") {
+      self.definitions.insert(string(id, path)?, rule.clone());
+    }
+    Ok(rule)
+  }
+
+  fn value(
+    &mut self,
+    node: &KdlNode,
+    path: &str,
+  ) -> Result<ValueRule, SchemaError> {
+    let mut rule = ValueRule::default();
+    for (kid, path) in with_paths(kids(node), path) {
+      match kid.name().value() {
+        "min" => rule.min = Some(count(arg(kid, &path)?, &path)?),
+        "max" => rule.max = Some(count(arg(kid, &path)?, &path)?),
+        _ => rule.checks.load(kid, &path)?,
+      }
+    }
+    Ok(rule)
+  }
+
+  fn prop(
+    &mut self,
+    node: &KdlNode,
+    path: &str,
+  ) -> Result<PropRule, SchemaError> {
+    let mut rule = PropRule {
+      key: args(node).next().map(|it| string(it, path)).transpose()?,
+      ..Default::default()
+    };
+    for (kid, path) in with_paths(kids(node), path) {
+      match kid.name().value() {
+        "required" => rule.required = arg(kid, &path)?.as_bool() == Some(true),
+        _ => rule.checks.load(kid, &path)?,
+      }
+    }
+    Ok(rule)
+  }
+}
+
+/// Pull the id out of `[id="..."]`
+fn ref_id(reference: &str) -> Option<String> {
+  let quoted = reference
+    .trim()
+    .strip_prefix('[')?
+    .strip_suffix(']')?
+    .trim()
+    .strip_prefix("id")?
+    .trim_start()
+    .strip_prefix('=')?
+    .trim();
+  let id = quoted.strip_prefix('"')?.strip_suffix('"')?;
+  Some(id.replace("\\\"", "\"").replace("\\\\", "\\"))
+}
+
+impl Checks {
+  fn load(&mut self, node: &KdlNode, path: &str) -> Result<(), SchemaError> {
+    let bound = match node.name().value() {
+      "type" => {
+        let ty = string(arg(node, path)?, path)?;
+        if !matches!(
+          ty.as_str(),
+          "string" | "number" | "integer" | "boolean" | "null"
+        ) {
+          return Err(invalid(path, format!("unknown type `{}`", ty)));
+        }
+        self.ty = Some(ty);
+        return Ok(());
+      }
+      "enum" => {
+        self.one_of = Some(args(node).cloned().collect());
+        return Ok(());
+      }
+      "tag" => {
+        let mut tag = Checks::default();
+        for (kid, path) in with_paths(kids(node), path) {
+          tag.load(kid, &path)?;
+        }
+        self.tag = Some(Box::new(tag));
+        return Ok(());
+      }
+      "pattern" => return self.load_pattern(node, path),
+      "min-length" => {
+        self.min_length = Some(count(arg(node, path)?, path)?);
+        return Ok(());
+      }
+      "max-length" => {
+        self.max_length = Some(count(arg(node, path)?, path)?);
+        return Ok(());
+      }
+      ">" => Bound::Gt,
+      ">=" => Bound::Ge,
+      "<" => Bound::Lt,
+      "<=" => Bound::Le,
+      "%" => Bound::MultipleOf,
+      _ => return Ok(()),
+    };
+    let limit = number(arg(node, path)?)
+      .ok_or_else(|| invalid(path, "expected a number"))?;
+    self.bounds.push((bound, limit));
+    Ok(())
+  }
+
+  #[cfg(feature = "regex")]
+  fn load_pattern(
+    &mut self,
+    node: &KdlNode,
+    path: &str,
+  ) -> Result<(), SchemaError> {
+    let pattern = string(arg(node, path)?, path)?;
+    let regex = regex::Regex::new(&pattern)
+      .map_err(|_| invalid(path, "not a valid regex"))?;
+    self.pattern = Some(regex);
+    Ok(())
+  }
+  #[cfg(not(feature = "regex"))]
+  fn load_pattern(
+    &mut self,
+    _node: &KdlNode,
+    path: &str,
+  ) -> Result<(), SchemaError> {
+    Err(invalid(path, "checking `pattern` needs the `regex` feature"))
+  }
+
+  /// Why the entry doesn't pass, if it doesn't
+  fn check(&self, entry: &KdlEntry) -> Option<String> {
+    if let Some(tag) = &self.tag {
+      let Some(ty) = entry.ty() else {
+        return Some("expected an annotation".to_owned());
+      };
+      let ty = KdlValue::String(ty.value().to_owned());
+      if let Some(problem) = tag.check_value(&ty) {
+        return Some(format!("annotation: {}", problem));
+      }
+    }
+    self.check_value(entry.value())
+  }
+
+  fn check_value(&self, value: &KdlValue) -> Option<String> {
+    if let Some(ty) = &self.ty {
+      let ok = match ty.as_str() {
+        "string" => value.is_string(),
+        "number" => value.is_integer() || value.is_float(),
+        "integer" => value.is_integer(),
+        "boolean" => value.is_bool(),
+        _ => value.is_null(),
+      };
+      if !ok {
+        return Some(format!("expected a {}, found {}", ty, show(value)));
+      }
+    }
+    if let Some(one_of) = &self.one_of {
+      if !one_of.contains(value) {
+        return Some(format!(
+          "{} isn't one of the allowed values",
+          show(value)
+        ));
+      }
+    }
+
+    if let KdlValue::String(s) = value {
+      let len = s.chars().count();
+      if let Some(min) = self.min_length.filter(|min| len < *min) {
+        return Some(format!(
+          "{} is shorter than {} characters",
+          show(value),
+          min
+        ));
+      }
+      if let Some(max) = self.max_length.filter(|max| len > *max) {
+        return Some(format!(
+          "{} is longer than {} characters",
+          show(value),
+          max
+        ));
+      }
+      #[cfg(feature = "regex")]
+      if let Some(pattern) = self.pattern.as_ref() {
+        if !pattern.is_match(s) {
+          return Some(format!(
+            "{} doesn't match `{}`",
+            show(value),
+            pattern
+          ));
+        }
+      }
+    }
+
+    if let Some(n) = number(value) {
+      for (bound, limit) in &self.bounds {
+        let (ok, relation) = match bound {
+          Bound::Gt => (n > *limit, "greater than"),
+          Bound::Ge => (n >= *limit, "at least"),
+          Bound::Lt => (n < *limit, "less than"),
+          Bound::Le => (n <= *limit, "at most"),
+          Bound::MultipleOf => (n % limit == 0.0, "a multiple of"),
+        };
+        if !ok {
+          return Some(format!("{} isn't {} {}", value, relation, limit));
+        }
+      }
+    }
+    None
+  }
+}
+
+struct Validator<'s> {
+  schema: &'s Schema,
+  violations: Vec<Violation>,
+}
+
+impl<'s> Validator<'s> {
+  fn report(&mut self, path: &str, message: impl Display) {
+    self.violations.push(Violation {
+      path: path.to_owned(),
+      message: message.to_string(),
+    });
+  }
+
+  fn name(&self, rule: &'s NodeRule) -> Option<&'s str> {
+    rule
+      .name
+      .as_deref()
+      .or_else(|| self.schema.body(rule).name.as_deref())
+  }
+
+  fn nodes(&mut self, rules: &'s ChildRules, nodes: &[KdlNode], path: &str) {
+    let mut counts = vec![0; rules.nodes.len()];
+    for (node, path) in with_paths(nodes, path) {
+      let name = node.name().value();
+      let position = |name| {
+        rules.nodes.iter().position(|rule| self.name(rule) == name)
+      };
+      let matched = position(Some(name)).or_else(|| position(None));
+      match matched {
+        Some(idx) => {
+          counts[idx] += 1;
+          self.node(&rules.nodes[idx], node, &path);
+        }
+        None if rules.other_nodes_allowed => {}
+        None => self.report(&path, format!("unexpected node `{}`", name)),
+      }
+    }
+
+    for (rule, count) in rules.nodes.iter().zip(counts) {
+      let body = self.schema.body(rule);
+      let what = match self.name(rule) {
+        Some(name) => format!("`{}` nodes", name),
+        None => "other nodes".to_owned(),
+      };
+      if let Some(min) = rule.min.or(body.min).filter(|min| count < *min) {
+        self.report(
+          path,
+          format!("expected at least {} {}, found {}", min, what, count),
+        );
+      }
+      if let Some(max) = rule.max.or(body.max).filter(|max| count > *max) {
+        self.report(
+          path,
+          format!("expected at most {} {}, found {}", max, what, count),
+        );
+      }
+    }
+  }
+
+  fn node(&mut self, rule: &'s NodeRule, node: &KdlNode, path: &str) {
+    let rule = self.schema.body(rule);
+
+    if let Some(values) = &rule.values {
+      let args: Vec<_> = arg_entries(node).collect();
+      if let Some(min) = values.min.filter(|min| args.len() < *min) {
+        self.report(
+          path,
+          format!("expected at least {} arguments, found {}", min, args.len()),
+        );
+      }
+      if let Some(max) = values.max.filter(|max| args.len() > *max) {
+        self.report(
+          path,
+          format!("expected at most {} arguments, found {}", max, args.len()),
+        );
+      }
+      for (idx, arg) in args.into_iter().enumerate() {
+        if let Some(problem) = values.checks.check(arg) {
+          self.report(path, format!("argument {}: {}", idx, problem));
+        }
+      }
+    }
+
+    for prop_rule in &rule.props {
+      let Some(key) = &prop_rule.key else { continue };
+      match prop_entry(node, key) {
+        Some(entry) => {
+          if let Some(problem) = prop_rule.checks.check(entry) {
+            self.report(path, format!("property `{}`: {}", key, problem));
+          }
+        }
+        None if prop_rule.required => {
+          self.report(path, format!("missing required property `{}`", key))
+        }
+        None => {}
+      }
+    }
+    let others = rule.props.iter().find(|it| it.key.is_none());
+    for entry in node.entries() {
+      let Some(key) = entry.name().map(|name| name.value()) else {
+        continue;
+      };
+      if rule.props.iter().any(|it| it.key.as_deref() == Some(key)) {
+        continue;
+      }
+      match others {
+        Some(others) => {
+          if let Some(problem) = others.checks.check(entry) {
+            self.report(path, format!("property `{}`: {}", key, problem));
+          }
+        }
+        None if rule.other_props_allowed => {}
+        None => self.report(path, format!("unexpected property `{}`", key)),
+      }
+    }
+
+    if let Some(children) = &rule.children {
+      self.nodes(children, kids(node), path);
+    }
+  }
+}
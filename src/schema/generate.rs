@@ -0,0 +1,422 @@
+//! Schemas from Rust types, found by tracing their `Deserialize` impls.
+
+use std::collections::BTreeSet;
+
+use heck::ToKebabCase;
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue};
+use serde::de::DeserializeOwned;
+
+use crate::{
+  reflect::{self, Container, Literal, Registry, Shape},
+  DeError,
+};
+
+/// Generate a schema for a document with one node named `name`, which
+/// deserializes into a `T`.
+///
+/// Fields that can be a single value can be properties or children with
+/// one argument, and everything else has to be a child. Fields that aren't
+/// `Option`s and don't have a default are required, except for single
+/// values, because a schema can't say "this property or that child".
+///
+/// Enums are only described when their variants can all be written as
+/// single values (unit variants, or newtype variants holding one in an
+/// annotation), since those are the only kind knurdy reads.
+pub fn generate<T: DeserializeOwned>(
+  name: &str,
+) -> Result<KdlDocument, DeError> {
+  let (shape, registry) = reflect::trace::<T>()?;
+  let mut gen = Generator {
+    registry: &registry,
+    defaults: reflect::defaults::<T>(&registry),
+    stack: Vec::new(),
+    recursive: BTreeSet::new(),
+  };
+
+  let mut root = rule_with("node", name);
+  push(&mut root, rule_with("min", 1));
+  push(&mut root, rule_with("max", 1));
+  gen.body(&shape, &mut root);
+
+  let mut document = KdlNode::new("document");
+  push(&mut document, root);
+
+  // Recursive types are written out once and referred to by id
+  let mut definitions = KdlNode::new("definitions");
+  let mut done = BTreeSet::new();
+  while let Some(&name) = gen.recursive.difference(&done).next() {
+    let mut def = KdlNode::new("node");
+    def.push(("id", name));
+    gen.stack.push(name);
+    gen.named(name, &mut def);
+    gen.stack.pop();
+    push(&mut definitions, def);
+    done.insert(name);
+  }
+  if definitions.children().is_some() {
+    push(&mut document, definitions);
+  }
+
+  let mut doc = KdlDocument::new();
+  doc.nodes_mut().push(document);
+  doc.autoformat();
+  Ok(doc)
+}
+
+/// Generate a JSON Schema for `T`'s serde data model, which is the JSON
+/// `serde_json` reads into a `T`.
+///
+/// This isn't what [`to_json_value`](crate::to_json_value) makes out of a
+/// document: that keeps KDL's names, like `a-kid` for `a_kid`, and doesn't
+/// know which nodes are meant to be lists of one.
+#[cfg(feature = "serde_json")]
+pub fn json_schema<T: DeserializeOwned>(
+) -> Result<serde_json::Value, DeError> {
+  let (shape, registry) = reflect::trace::<T>()?;
+  let defaults = reflect::defaults::<T>(&registry);
+  let mut schema = json::shape(&shape);
+  let defs = registry
+    .iter()
+    .map(|(name, it)| {
+      let optional = |field| defaults.contains(&(*name, field));
+      (name.to_string(), json::container(it, optional))
+    })
+    .collect();
+  schema["$schema"] = "https://json-schema.org/draft/2020-12/schema".into();
+  schema["$defs"] = serde_json::Value::Object(defs);
+  Ok(schema)
+}
+
+fn rule_with(kind: &str, arg: impl Into<KdlValue>) -> KdlNode {
+  let mut node = KdlNode::new(kind);
+  node.push(arg.into());
+  node
+}
+
+fn push(node: &mut KdlNode, kid: KdlNode) {
+  node.ensure_children().nodes_mut().push(kid);
+}
+
+/// The checks for a value written as `literal`
+fn checks(literal: &Literal) -> Vec<KdlNode> {
+  let shape = match literal {
+    Literal::Scalar(shape) => shape,
+    Literal::Enum(variants) => return enum_checks(variants),
+  };
+  let ty = |ty: &str| rule_with("type", ty);
+  let int = |min: i128, max: i128| {
+    vec![ty("integer"), rule_with(">=", min), rule_with("<=", max)]
+  };
+  match shape {
+    Shape::Unit => vec![ty("null")],
+    Shape::Bool => vec![ty("boolean")],
+    Shape::I8 => int(i8::MIN.into(), i8::MAX.into()),
+    Shape::I16 => int(i16::MIN.into(), i16::MAX.into()),
+    Shape::I32 => int(i32::MIN.into(), i32::MAX.into()),
+    Shape::U8 => int(0, u8::MAX.into()),
+    Shape::U16 => int(0, u16::MAX.into()),
+    Shape::U32 => int(0, u32::MAX.into()),
+    Shape::I64 | Shape::I128 => vec![ty("integer")],
+    Shape::U64 | Shape::U128 => vec![ty("integer"), rule_with(">=", 0)],
+    Shape::F32 | Shape::F64 => vec![ty("number")],
+    Shape::Char => vec![
+      ty("string"),
+      rule_with("min-length", 1),
+      rule_with("max-length", 1),
+    ],
+    Shape::Str => vec![ty("string")],
+    // Nothing else is a scalar
+    _ => Vec::new(),
+  }
+}
+
+fn enum_checks(variants: &[(&'static str, Option<Literal>)]) -> Vec<KdlNode> {
+  let (units, tags): (Vec<_>, Vec<_>) =
+    variants.iter().partition(|(_, inner)| inner.is_none());
+  let one_of = |variants: Vec<&(&str, Option<Literal>)>| {
+    let mut node = KdlNode::new("enum");
+    let names = variants.into_iter().map(|(name, _)| KdlEntry::new(*name));
+    node.entries_mut().extend(names);
+    node
+  };
+  match (units.is_empty(), tags.is_empty()) {
+    (false, true) => vec![rule_with("type", "string"), one_of(units)],
+    (true, false) => {
+      let mut tag = KdlNode::new("tag");
+      push(&mut tag, one_of(tags));
+      vec![tag]
+    }
+    // Can't say "either a string or annotated" in a schema
+    _ => Vec::new(),
+  }
+}
+
+struct Generator<'r> {
+  registry: &'r Registry,
+  /// Struct fields that can be left out
+  defaults: BTreeSet<(&'static str, &'static str)>,
+  /// Containers we're inside of
+  stack: Vec<&'static str>,
+  /// Containers that showed up inside themselves
+  recursive: BTreeSet<&'static str>,
+}
+
+impl<'r> Generator<'r> {
+  /// The checks for a shape that's written as a single value, or `None`
+  /// if it isn't one.
+  fn literal(&self, shape: &Shape) -> Option<Vec<KdlNode>> {
+    reflect::literal(shape, self.registry).map(|it| checks(&it))
+  }
+
+  /// Describe a node holding `shape` by adding to its rule.
+  fn body(&mut self, shape: &Shape, rule: &mut KdlNode) {
+    if let Some(checks) = self.literal(shape) {
+      let mut value = KdlNode::new("value");
+      push(&mut value, rule_with("min", 1));
+      push(&mut value, rule_with("max", 1));
+      for check in checks {
+        push(&mut value, check);
+      }
+      push(rule, value);
+      return;
+    }
+
+    match shape {
+      Shape::Option(inner) => self.body(inner, rule),
+      Shape::Seq(inner) => self.seq(inner, None, rule),
+      Shape::Tuple(items) => self.tuple(items, rule),
+      // A list of integer arguments
+      Shape::Bytes => self.seq(&Shape::U8, None, rule),
+      Shape::Map(_, value) => {
+        if let Some(checks) = self.literal(value) {
+          let mut prop = KdlNode::new("prop");
+          for check in checks {
+            push(&mut prop, check);
+          }
+          push(rule, prop);
+        }
+        let mut kid = KdlNode::new("node");
+        self.body(value, &mut kid);
+        let mut children = KdlNode::new("children");
+        push(&mut children, kid);
+        push(rule, children);
+      }
+      Shape::Named(name) => {
+        if self.stack.contains(name) {
+          rule.push(("ref", format!("[id=\"{}\"]", name)));
+          self.recursive.insert(name);
+        } else {
+          self.stack.push(name);
+          self.named(name, rule);
+          self.stack.pop();
+        }
+      }
+      // Could be anything, or never got traced
+      _ => {}
+    }
+  }
+
+  fn named(&mut self, name: &str, rule: &mut KdlNode) {
+    let Some(container) = self.registry.get(name) else {
+      return;
+    };
+    match container {
+      Container::Unit => {
+        let mut value = KdlNode::new("value");
+        push(&mut value, rule_with("max", 0));
+        push(rule, value);
+      }
+      Container::Newtype(inner) => self.body(inner, rule),
+      Container::Tuple(items) => self.tuple(items, rule),
+      Container::Struct(fields) => {
+        let mut children = KdlNode::new("children");
+        for (field, shape) in fields {
+          let key = field.to_kebab_case();
+          let required = !matches!(shape, Shape::Option(_))
+            && !self.defaults.contains(&(name, *field));
+          if let Some(checks) = self.literal(shape) {
+            // `key=value` or `key value`
+            let mut prop = rule_with("prop", key.clone());
+            for check in checks {
+              push(&mut prop, check);
+            }
+            push(rule, prop);
+            let mut kid = rule_with("node", key);
+            push(&mut kid, rule_with("max", 1));
+            self.body(shape, &mut kid);
+            push(&mut children, kid);
+          } else {
+            let mut kid = rule_with("node", key);
+            if required {
+              push(&mut kid, rule_with("min", 1));
+            }
+            push(&mut kid, rule_with("max", 1));
+            self.body(shape, &mut kid);
+            push(&mut children, kid);
+          }
+        }
+        if children.children().is_some() {
+          push(rule, children);
+        }
+      }
+      // Enums that aren't literals can't be read from a node
+      Container::Enum(_) => {}
+    }
+  }
+
+  /// Arguments if the items are literals, and `-` children if not
+  fn seq(&mut self, item: &Shape, size: Option<usize>, rule: &mut KdlNode) {
+    if let Some(checks) = self.literal(item) {
+      let mut value = KdlNode::new("value");
+      if let Some(size) = size {
+        push(&mut value, rule_with("min", size as i128));
+        push(&mut value, rule_with("max", size as i128));
+      }
+      for check in checks {
+        push(&mut value, check);
+      }
+      push(rule, value);
+    } else {
+      let mut kid = rule_with("node", "-");
+      if let Some(size) = size {
+        push(&mut kid, rule_with("min", size as i128));
+        push(&mut kid, rule_with("max", size as i128));
+      }
+      self.body(item, &mut kid);
+      let mut children = KdlNode::new("children");
+      push(&mut children, kid);
+      push(rule, children);
+    }
+  }
+
+  fn tuple(&mut self, items: &[Shape], rule: &mut KdlNode) {
+    match items {
+      [first, rest @ ..] if rest.iter().all(|it| it == first) => {
+        self.seq(first, Some(items.len()), rule)
+      }
+      _ => {
+        // Mixed types can only be counted
+        let kind = if items.iter().all(|it| self.literal(it).is_some()) {
+          "value"
+        } else {
+          "node"
+        };
+        let mut count = match kind {
+          "node" => rule_with("node", "-"),
+          _ => KdlNode::new("value"),
+        };
+        push(&mut count, rule_with("min", items.len() as i128));
+        push(&mut count, rule_with("max", items.len() as i128));
+        if kind == "node" {
+          let mut children = KdlNode::new("children");
+          push(&mut children, count);
+          push(rule, children);
+        } else {
+          push(rule, count);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(feature = "serde_json")]
+mod json {
+  use serde_json::{json, Map, Value};
+
+  use crate::reflect::{Container, Shape, Variant};
+
+  pub(super) fn shape(it: &Shape) -> Value {
+    match it {
+      Shape::Unknown | Shape::Any => json!({}),
+      Shape::Unit => json!({ "type": "null" }),
+      Shape::Bool => json!({ "type": "boolean" }),
+      Shape::U8 | Shape::U16 | Shape::U32 | Shape::U64 | Shape::U128 => {
+        json!({ "type": "integer", "minimum": 0 })
+      }
+      Shape::I8 | Shape::I16 | Shape::I32 | Shape::I64 | Shape::I128 => {
+        json!({ "type": "integer" })
+      }
+      Shape::F32 | Shape::F64 => json!({ "type": "number" }),
+      Shape::Char => {
+        json!({ "type": "string", "minLength": 1, "maxLength": 1 })
+      }
+      Shape::Str => json!({ "type": "string" }),
+      Shape::Bytes => json!({ "type": "array", "items": shape(&Shape::U8) }),
+      Shape::Option(inner) => {
+        json!({ "anyOf": [shape(inner), { "type": "null" }] })
+      }
+      Shape::Seq(inner) => json!({ "type": "array", "items": shape(inner) }),
+      Shape::Map(_, value) => json!({
+        "type": "object",
+        "additionalProperties": shape(value),
+      }),
+      Shape::Tuple(items) => tuple(items),
+      Shape::Named(name) => json!({ "$ref": format!("#/$defs/{}", name) }),
+    }
+  }
+
+  fn tuple(items: &[Shape]) -> Value {
+    json!({
+      "type": "array",
+      "prefixItems": items.iter().map(shape).collect::<Vec<_>>(),
+      "minItems": items.len(),
+      "maxItems": items.len(),
+    })
+  }
+
+  fn object(
+    fields: &[(&'static str, Shape)],
+    optional: impl Fn(&'static str) -> bool,
+  ) -> Value {
+    let properties: Map<_, _> = fields
+      .iter()
+      .map(|(name, it)| (name.to_string(), shape(it)))
+      .collect();
+    let required: Vec<_> = fields
+      .iter()
+      .filter(|(name, it)| !matches!(it, Shape::Option(_)) && !optional(name))
+      .map(|(name, _)| name)
+      .collect();
+    json!({
+      "type": "object",
+      "properties": properties,
+      "required": required,
+      "additionalProperties": false,
+    })
+  }
+
+  /// `optional` says which of a struct's fields can be left out
+  pub(super) fn container(
+    it: &Container,
+    optional: impl Fn(&'static str) -> bool,
+  ) -> Value {
+    match it {
+      Container::Unit => json!({ "type": "null" }),
+      Container::Newtype(inner) => shape(inner),
+      Container::Tuple(items) => tuple(items),
+      Container::Struct(fields) => object(fields, optional),
+      // Externally tagged, like serde_json
+      Container::Enum(variants) => {
+        let variants: Vec<_> = variants
+          .iter()
+          .map(|(name, variant)| {
+            let inner = match variant {
+              Variant::Unit => return json!({ "const": name }),
+              Variant::Unknown => json!({}),
+              Variant::Newtype(inner) => shape(inner),
+              Variant::Tuple(items) => tuple(items),
+              Variant::Struct(fields) => object(fields, |_| false),
+            };
+            json!({
+              "type": "object",
+              "properties": { *name: inner },
+              "required": [name],
+              "additionalProperties": false,
+            })
+          })
+          .collect();
+        json!({ "oneOf": variants })
+      }
+    }
+  }
+}
@@ -0,0 +1,513 @@
+//! Serializing into a [`Value`], the first step of writing KDL.
+//!
+//! The shapes are the same ones serde_json uses: enums are externally
+//! tagged, and unit variants are just their name.
+
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::{SerError, Value};
+
+pub(crate) fn to_value<T: Serialize + ?Sized>(
+  value: &T,
+) -> Result<Value, SerError> {
+  value.serialize(ValueSerializer)
+}
+
+/// Wrap a value up as `{variant: value}`
+fn tagged(variant: &'static str, value: Value) -> Value {
+  Value::Map(vec![(variant.to_owned(), value)])
+}
+
+pub(crate) struct ValueSerializer;
+
+macro_rules! serialize_int {
+  (@ $ty:ident) => {
+    paste::paste! {
+      fn [< serialize_ $ty >](self, v: $ty) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::Int(v.into()))
+      }
+    }
+  };
+  ( $($ty:ident)* ) => {
+    $(
+      serialize_int!(@ $ty);
+    )*
+  };
+}
+
+impl ser::Serializer for ValueSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  type SerializeSeq = SeqSerializer;
+  type SerializeTuple = SeqSerializer;
+  type SerializeTupleStruct = SeqSerializer;
+  type SerializeTupleVariant = SeqSerializer;
+  type SerializeMap = MapSerializer;
+  type SerializeStruct = MapSerializer;
+  type SerializeStructVariant = MapSerializer;
+
+  serialize_int! { i8 i16 i32 i64 i128 u8 u16 u32 u64 }
+
+  fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+    // Too big for a `Value::Int`, so it's written as a string
+    match i128::try_from(v) {
+      Ok(it) => Ok(Value::Int(it)),
+      Err(_) => Ok(Value::Annotated(
+        "u128".to_owned(),
+        Box::new(Value::String(v.to_string())),
+      )),
+    }
+  }
+
+  fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Bool(v))
+  }
+  fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Float(v.into()))
+  }
+  fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Float(v))
+  }
+  fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::String(v.to_string()))
+  }
+  fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::String(v.to_owned()))
+  }
+  fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Bytes(v.to_owned()))
+  }
+
+  fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Unit)
+  }
+  fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    value.serialize(self)
+  }
+  fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Unit)
+  }
+  fn serialize_unit_struct(
+    self,
+    _name: &'static str,
+  ) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::Unit)
+  }
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+  ) -> Result<Self::Ok, Self::Error> {
+    Ok(Value::String(variant.to_owned()))
+  }
+
+  fn serialize_newtype_struct<T>(
+    self,
+    _name: &'static str,
+    value: &T,
+  ) -> Result<Self::Ok, Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    value.serialize(self)
+  }
+  fn serialize_newtype_variant<T>(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    value: &T,
+  ) -> Result<Self::Ok, Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    Ok(tagged(variant, to_value(value)?))
+  }
+
+  fn serialize_seq(
+    self,
+    len: Option<usize>,
+  ) -> Result<Self::SerializeSeq, Self::Error> {
+    Ok(SeqSerializer {
+      items: Vec::with_capacity(len.unwrap_or(0)),
+      variant: None,
+    })
+  }
+  fn serialize_tuple(
+    self,
+    len: usize,
+  ) -> Result<Self::SerializeTuple, Self::Error> {
+    self.serialize_seq(Some(len))
+  }
+  fn serialize_tuple_struct(
+    self,
+    _name: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+    self.serialize_seq(Some(len))
+  }
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+    Ok(SeqSerializer {
+      items: Vec::with_capacity(len),
+      variant: Some(variant),
+    })
+  }
+
+  fn serialize_map(
+    self,
+    len: Option<usize>,
+  ) -> Result<Self::SerializeMap, Self::Error> {
+    Ok(MapSerializer {
+      entries: Vec::with_capacity(len.unwrap_or(0)),
+      key: None,
+      variant: None,
+    })
+  }
+  fn serialize_struct(
+    self,
+    _name: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeStruct, Self::Error> {
+    self.serialize_map(Some(len))
+  }
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeStructVariant, Self::Error> {
+    Ok(MapSerializer {
+      entries: Vec::with_capacity(len),
+      key: None,
+      variant: Some(variant),
+    })
+  }
+}
+
+pub(crate) struct SeqSerializer {
+  items: Vec<Value>,
+  /// Set for tuple variants
+  variant: Option<&'static str>,
+}
+
+impl SeqSerializer {
+  fn push<T>(&mut self, value: &T) -> Result<(), SerError>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.items.push(to_value(value)?);
+    Ok(())
+  }
+
+  fn finish(self) -> Value {
+    let seq = Value::Seq(self.items);
+    match self.variant {
+      Some(variant) => tagged(variant, seq),
+      None => seq,
+    }
+  }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.push(value)
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.push(value)
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.push(value)
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.push(value)
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+pub(crate) struct MapSerializer {
+  entries: Vec<(String, Value)>,
+  /// The key waiting on its value
+  key: Option<String>,
+  /// Set for struct variants
+  variant: Option<&'static str>,
+}
+
+impl MapSerializer {
+  fn finish(self) -> Value {
+    let map = Value::Map(self.entries);
+    match self.variant {
+      Some(variant) => tagged(variant, map),
+      None => map,
+    }
+  }
+}
+
+impl ser::SerializeMap for MapSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.key = Some(key.serialize(KeySerializer)?);
+    Ok(())
+  }
+  fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    let key = self.key.take().ok_or_else(|| {
+      <SerError as ser::Error>::custom("map value serialized before its key")
+    })?;
+    self.entries.push((key, to_value(value)?));
+    Ok(())
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_field<T>(
+    &mut self,
+    key: &'static str,
+    value: &T,
+  ) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.entries.push((key.to_owned(), to_value(value)?));
+    Ok(())
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+  type Ok = Value;
+  type Error = SerError;
+
+  fn serialize_field<T>(
+    &mut self,
+    key: &'static str,
+    value: &T,
+  ) -> Result<(), Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    self.entries.push((key.to_owned(), to_value(value)?));
+    Ok(())
+  }
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.finish())
+  }
+}
+
+/// Turns map keys into the strings node and property names have to be
+struct KeySerializer;
+
+macro_rules! serialize_key {
+  (@ $ty:ident) => {
+    paste::paste! {
+      fn [< serialize_ $ty >](self, v: $ty) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+      }
+    }
+  };
+  ( $($ty:ident)* ) => {
+    $(
+      serialize_key!(@ $ty);
+    )*
+  };
+}
+
+impl ser::Serializer for KeySerializer {
+  type Ok = String;
+  type Error = SerError;
+
+  type SerializeSeq = Impossible<String, SerError>;
+  type SerializeTuple = Impossible<String, SerError>;
+  type SerializeTupleStruct = Impossible<String, SerError>;
+  type SerializeTupleVariant = Impossible<String, SerError>;
+  type SerializeMap = Impossible<String, SerError>;
+  type SerializeStruct = Impossible<String, SerError>;
+  type SerializeStructVariant = Impossible<String, SerError>;
+
+  serialize_key! {
+    i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 bool char
+  }
+
+  fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+    Ok(v.to_owned())
+  }
+
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+  ) -> Result<Self::Ok, Self::Error> {
+    Ok(variant.to_owned())
+  }
+  fn serialize_newtype_struct<T>(
+    self,
+    _name: &'static str,
+    value: &T,
+  ) -> Result<Self::Ok, Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    value.serialize(self)
+  }
+
+  fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_some<T>(self, _value: &T) -> Result<Self::Ok, Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_unit_struct(
+    self,
+    _name: &'static str,
+  ) -> Result<Self::Ok, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_newtype_variant<T>(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    _variant: &'static str,
+    _value: &T,
+  ) -> Result<Self::Ok, Self::Error>
+  where
+    T: Serialize + ?Sized,
+  {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_seq(
+    self,
+    _len: Option<usize>,
+  ) -> Result<Self::SerializeSeq, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_tuple(
+    self,
+    _len: usize,
+  ) -> Result<Self::SerializeTuple, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_tuple_struct(
+    self,
+    _name: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_map(
+    self,
+    _len: Option<usize>,
+  ) -> Result<Self::SerializeMap, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_struct(
+    self,
+    _name: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeStruct, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeStructVariant, Self::Error> {
+    Err(SerError::InvalidKey)
+  }
+}
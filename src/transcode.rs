@@ -0,0 +1,117 @@
+//! Writing KDL out in any format serde can serialize to, without a typed
+//! struct in between.
+
+use kdl::{KdlDocument, KdlNode};
+use serde::{
+  ser::{Error, Serialize, Serializer},
+  Deserialize,
+};
+
+use crate::{DeError, KdlAnnotatedValueDeser, Value};
+
+/// Write a node to any `Serializer`, shaped the same way knurdy shapes it
+/// when deserializing. See [`Value`] for how annotations come out.
+pub fn transcode<S: Serializer>(
+  node: &KdlNode,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  node_value(node).map_err(S::Error::custom)?.serialize(serializer)
+}
+
+/// Write a whole document to any `Serializer`.
+///
+/// The document is treated like the children of a node: a map from node
+/// names to nodes, or a sequence if every node is named `-`. Sibling nodes
+/// that share a name (or a name with a property) are gathered into a
+/// sequence under that name, so none of them get lost.
+pub fn transcode_document<S: Serializer>(
+  doc: &KdlDocument,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  document_value(doc)
+    .map_err(S::Error::custom)?
+    .serialize(serializer)
+}
+
+/// Turn a document into JSON, following [`transcode_document`].
+#[cfg(feature = "serde_json")]
+pub fn to_json_value(
+  doc: &KdlDocument,
+) -> Result<serde_json::Value, serde_json::Error> {
+  transcode_document(doc, serde_json::value::Serializer)
+}
+
+/// Like deserializing a `Value`, except a node with a single argument is
+/// just that argument instead of a sequence of one, all the way down.
+fn node_value(node: &KdlNode) -> Result<Value, DeError> {
+  let entry_value =
+    |entry| Value::deserialize(KdlAnnotatedValueDeser::new(entry));
+  let mut args = Vec::new();
+  let mut props = Vec::new();
+  for entry in node.entries() {
+    match entry.name() {
+      Some(name) => props.push((name.value().to_owned(), entry_value(entry)?)),
+      None => args.push(entry_value(entry)?),
+    }
+  }
+
+  let value = match (args.len(), props.is_empty(), node.children()) {
+    (0, true, None) => Value::Unit,
+    (1, true, None) => args.pop().unwrap(),
+    (_, true, None) => Value::Seq(args),
+    (0, true, Some(kids)) => document_value(kids)?,
+    (0, false, kids) => {
+      let mut map = props;
+      if let Some(kids) = kids {
+        map.extend(children(kids)?);
+      }
+      Value::Map(group(map))
+    }
+    (_, _, kids) => Value::Node {
+      args,
+      props,
+      children: match kids {
+        Some(kids) => group(children(kids)?),
+        None => Vec::new(),
+      },
+    },
+  };
+  Ok(value)
+}
+
+/// Nodes named `-` make a sequence, and anything else is a map
+fn document_value(doc: &KdlDocument) -> Result<Value, DeError> {
+  let kids = children(doc)?;
+  if !kids.is_empty() && kids.iter().all(|(name, _)| name == "-") {
+    Ok(Value::Seq(kids.into_iter().map(|(_, kid)| kid).collect()))
+  } else {
+    Ok(Value::Map(group(kids)))
+  }
+}
+
+fn children(doc: &KdlDocument) -> Result<Vec<(String, Value)>, DeError> {
+  doc
+    .nodes()
+    .iter()
+    .map(|kid| Ok((kid.name().value().to_owned(), node_value(kid)?)))
+    .collect()
+}
+
+/// Gather entries with the same key into a sequence, in the order each key
+/// first shows up
+fn group(entries: Vec<(String, Value)>) -> Vec<(String, Value)> {
+  let mut groups: Vec<(String, Vec<Value>)> = Vec::new();
+  for (key, value) in entries {
+    match groups.iter_mut().find(|(it, _)| *it == key) {
+      Some((_, group)) => group.push(value),
+      None => groups.push((key, vec![value])),
+    }
+  }
+  groups
+    .into_iter()
+    .map(|(key, mut group)| match group.len() {
+      1 => (key, group.pop().unwrap()),
+      _ => (key, Value::Seq(group)),
+    })
+    .collect()
+}
@@ -0,0 +1,768 @@
+use std::{borrow::Cow, fmt};
+
+use heck::ToSnekCase;
+use kdl::KdlValue;
+use serde::de::{
+  self, Deserializer, Error, IntoDeserializer, Unexpected, Visitor,
+};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde::Deserialize;
+
+use crate::{
+  annotation::{self, BigInt},
+  encoding,
+  key::KeyDeser,
+  literal, DeError,
+};
+
+/// Name of the newtype struct `Value` asks for, so knurdy's deserializers
+/// know to hand over the information other visitors don't get to see
+/// (annotations, and nodes with both arguments and properties/children).
+pub(crate) const VALUE_TOKEN: &str = "$knurdy::private::Value";
+/// First key of the map describing a `Value::Annotated`
+const ANNOTATED_TOKEN: &str = "$knurdy::private::Annotated";
+/// First key of the map describing a `Value::Node`
+const NODE_TOKEN: &str = "$knurdy::private::Node";
+
+/// An owned, dynamically typed value in knurdy's data model.
+///
+/// Nodes are turned into values with the same rules `KdlNodeDeser` uses:
+/// nodes with nothing in them are `Unit`, nodes with only arguments or only
+/// `-` children are `Seq`s, and nodes with properties and/or children are
+/// `Map`s. Nodes that fit none of those are kept whole as `Node`.
+///
+/// A `Value` is also a `Deserializer`, so it can be turned back into a typed
+/// value later, and it's `Serialize` so it can be written out in another
+/// format. When serialized, annotations the KDL spec reserves are dropped
+/// (they're only type hints), and any other annotation is treated as an enum
+/// variant, so `(Circle)5` becomes `{"Circle": 5}` in JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Unit,
+  Bool(bool),
+  /// Integers that don't fit in an `i128` are kept as annotated strings.
+  Int(i128),
+  Float(f64),
+  String(String),
+  Bytes(Vec<u8>),
+  Seq(Vec<Value>),
+  /// Properties and children, in document order. Keys can repeat.
+  Map(Vec<(String, Value)>),
+  /// A value with a type annotation, like `(base64)"AAAA"`.
+  Annotated(String, Box<Value>),
+  /// A node with both arguments and properties or children.
+  Node {
+    args: Vec<Value>,
+    props: Vec<(String, Value)>,
+    children: Vec<(String, Value)>,
+  },
+}
+
+impl Value {
+  /// Get the first value in a map with the given key.
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    match self {
+      Value::Map(entries) => {
+        entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+      }
+      Value::Node {
+        props, children, ..
+      } => props
+        .iter()
+        .chain(children.iter())
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v),
+      _ => None,
+    }
+  }
+
+  /// Nodes with exactly one argument become one-element sequences;
+  /// in places where a scalar is wanted, look through them like
+  /// `KdlNodeDeser` does.
+  fn unwrap_single(self) -> Self {
+    match self {
+      Value::Seq(mut seq) if seq.len() == 1 => seq.pop().unwrap(),
+      it => it,
+    }
+  }
+
+  fn unexpected(&self) -> Unexpected<'_> {
+    match self {
+      Value::Unit => Unexpected::Unit,
+      Value::Bool(b) => Unexpected::Bool(*b),
+      Value::Int(it) => match i64::try_from(*it) {
+        Ok(it) => Unexpected::Signed(it),
+        Err(_) => Unexpected::Other("large integer"),
+      },
+      Value::Float(f) => Unexpected::Float(*f),
+      Value::String(s) => Unexpected::Str(s),
+      Value::Bytes(b) => Unexpected::Bytes(b),
+      Value::Seq(_) => Unexpected::Seq,
+      Value::Map(_) => Unexpected::Map,
+      Value::Annotated(_, v) => v.unexpected(),
+      Value::Node { .. } => Unexpected::Other(
+        "node with both arguments and properties/children",
+      ),
+    }
+  }
+}
+
+impl From<KdlValue> for Value {
+  fn from(value: KdlValue) -> Self {
+    match value {
+      KdlValue::String(s) => Value::String(s),
+      KdlValue::Integer(it) => Value::Int(it),
+      KdlValue::Float(f) => Value::Float(f),
+      KdlValue::Bool(b) => Value::Bool(b),
+      KdlValue::Null => Value::Unit,
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Value {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_newtype_struct(VALUE_TOKEN, ValueVisitor)
+  }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("any knurdy value")
+  }
+
+  fn visit_unit<E: Error>(self) -> Result<Value, E> {
+    Ok(Value::Unit)
+  }
+  fn visit_none<E: Error>(self) -> Result<Value, E> {
+    Ok(Value::Unit)
+  }
+  fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    Value::deserialize(deserializer)
+  }
+  fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Value, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(self)
+  }
+
+  fn visit_bool<E: Error>(self, v: bool) -> Result<Value, E> {
+    Ok(Value::Bool(v))
+  }
+  fn visit_i64<E: Error>(self, v: i64) -> Result<Value, E> {
+    Ok(Value::Int(v.into()))
+  }
+  fn visit_u64<E: Error>(self, v: u64) -> Result<Value, E> {
+    Ok(Value::Int(v.into()))
+  }
+  fn visit_i128<E: Error>(self, v: i128) -> Result<Value, E> {
+    Ok(Value::Int(v))
+  }
+  fn visit_u128<E: Error>(self, v: u128) -> Result<Value, E> {
+    match i128::try_from(v) {
+      Ok(it) => Ok(Value::Int(it)),
+      Err(_) => Err(E::invalid_value(Unexpected::Other("u128"), &self)),
+    }
+  }
+  fn visit_f64<E: Error>(self, v: f64) -> Result<Value, E> {
+    Ok(Value::Float(v))
+  }
+
+  fn visit_str<E: Error>(self, v: &str) -> Result<Value, E> {
+    Ok(Value::String(v.to_owned()))
+  }
+  fn visit_string<E: Error>(self, v: String) -> Result<Value, E> {
+    Ok(Value::String(v))
+  }
+  fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Value, E> {
+    Ok(Value::Bytes(v.to_owned()))
+  }
+  fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Value, E> {
+    Ok(Value::Bytes(v))
+  }
+
+  fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+  where
+    A: de::SeqAccess<'de>,
+  {
+    let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(it) = seq.next_element()? {
+      out.push(it);
+    }
+    Ok(Value::Seq(out))
+  }
+
+  fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+  where
+    A: de::MapAccess<'de>,
+  {
+    let mut out = Vec::with_capacity(map.size_hint().unwrap_or(0));
+    while let Some(it) = map.next_entry::<String, Value>()? {
+      out.push(it);
+    }
+
+    // Check if this was one of our special maps
+    let magic = out.first().map(|(k, _)| k.as_str());
+    if magic == Some(ANNOTATED_TOKEN) {
+      if let [(_, Value::String(ann)), (_, inner)] = out.as_mut_slice() {
+        let inner = std::mem::replace(inner, Value::Unit);
+        return Ok(Value::Annotated(std::mem::take(ann), Box::new(inner)));
+      }
+    } else if magic == Some(NODE_TOKEN) {
+      if let [
+        (_, Value::Seq(args)),
+        (_, Value::Map(props)),
+        (_, Value::Map(children)),
+      ] = out.as_mut_slice()
+      {
+        return Ok(Value::Node {
+          args: std::mem::take(args),
+          props: std::mem::take(props),
+          children: std::mem::take(children),
+        });
+      }
+    }
+    Ok(Value::Map(out))
+  }
+}
+
+/// Forward to `deserialize_any`, looking through single-element sequences
+macro_rules! scalar_to_any {
+  (@ $ty:ident) => {
+    paste::paste! {
+      fn [< deserialize_ $ty >]<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+      where
+        V: Visitor<'de>,
+      {
+        self.unwrap_single().deserialize_any(visitor)
+      }
+    }
+  };
+  ( $($ty:ident)* ) => {
+    $(
+      scalar_to_any!(@ $ty);
+    )*
+  };
+}
+
+impl<'de> Deserializer<'de> for Value {
+  type Error = DeError;
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self {
+      Value::Unit => visitor.visit_unit(),
+      Value::Bool(b) => visitor.visit_bool(b),
+      Value::Int(it) => {
+        if let Ok(it) = i64::try_from(it) {
+          visitor.visit_i64(it)
+        } else if let Ok(it) = u64::try_from(it) {
+          visitor.visit_u64(it)
+        } else {
+          visitor.visit_i128(it)
+        }
+      }
+      Value::Float(f) => visitor.visit_f64(f),
+      Value::String(s) => visitor.visit_string(s),
+      Value::Bytes(b) => visitor.visit_byte_buf(b),
+      Value::Seq(seq) => visitor.visit_seq(SeqValueDeser(seq.into_iter())),
+      Value::Map(map) => visitor.visit_map(MapValueDeser::new(map, None)),
+      Value::Annotated(ann, inner) => {
+        // Ints too big to be a `Value::Int` stay as strings
+        if let Value::String(s) = &*inner {
+          if let Some(Ok(int)) = annotation::parse_string_int(&ann, s) {
+            return literal::visit_big_int(int, visitor);
+          }
+        }
+        inner.deserialize_any(visitor)
+      }
+      oh_no @ Value::Node { .. } => {
+        Err(DeError::invalid_type(oh_no.unexpected(), &visitor))
+      }
+    }
+  }
+
+  scalar_to_any! {
+    bool i8 i16 i32 i64 i128 u16 u32 u64 u128 f32 f64
+    char str string identifier
+  }
+
+  fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self.unwrap_single() {
+      Value::String(s) => match s.as_bytes() {
+        [b] => visitor.visit_u8(*b),
+        _ => Err(DeError::ByteAnnotationLen),
+      },
+      it => it.deserialize_any(visitor),
+    }
+  }
+
+  fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    // A list of integers works as bytes too
+    if let Value::Seq(seq) = &self {
+      if !seq.is_empty() && seq.iter().all(|it| matches!(it, Value::Int(_))) {
+        let bytes = seq
+          .iter()
+          .map(|it| match it {
+            Value::Int(it) => u8::try_from(*it).map_err(DeError::from),
+            _ => unreachable!(),
+          })
+          .collect::<Result<_, _>>()?;
+        return visitor.visit_byte_buf(bytes);
+      }
+    }
+
+    match self.unwrap_single() {
+      Value::Annotated(ann, inner) => match *inner {
+        Value::String(s) => match encoding::decode(&ann, &s) {
+          Some(decoded) => visitor.visit_byte_buf(decoded?),
+          None => visitor.visit_byte_buf(s.into_bytes()),
+        },
+        inner => inner.deserialize_bytes(visitor),
+      },
+      Value::String(s) => visitor.visit_byte_buf(s.into_bytes()),
+      Value::Bytes(b) => visitor.visit_byte_buf(b),
+      oh_no => Err(DeError::invalid_type(oh_no.unexpected(), &visitor)),
+    }
+  }
+  fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_bytes(visitor)
+  }
+
+  fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self {
+      Value::Unit => visitor.visit_none(),
+      Value::Seq(ref seq) if matches!(seq.as_slice(), [Value::Unit]) => {
+        visitor.visit_none()
+      }
+      it => visitor.visit_some(it),
+    }
+  }
+
+  fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self {
+      Value::Unit => visitor.visit_unit(),
+      oh_no => Err(DeError::invalid_type(oh_no.unexpected(), &visitor)),
+    }
+  }
+  fn deserialize_unit_struct<V>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_unit(visitor)
+  }
+
+  fn deserialize_newtype_struct<V>(
+    self,
+    name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    if name != VALUE_TOKEN {
+      return visitor.visit_newtype_struct(self);
+    }
+    // Another `Value` is asking, so spell out the parts that
+    // `deserialize_any` would lose
+    match self {
+      Value::Annotated(ann, inner) => {
+        let map = vec![
+          (ANNOTATED_TOKEN.to_owned(), Value::String(ann)),
+          (String::new(), *inner),
+        ];
+        visitor.visit_map(MapValueDeser::new(map, None))
+      }
+      Value::Node {
+        args,
+        props,
+        children,
+      } => {
+        let map = vec![
+          (NODE_TOKEN.to_owned(), Value::Seq(args)),
+          (String::new(), Value::Map(props)),
+          (String::new(), Value::Map(children)),
+        ];
+        visitor.visit_map(MapValueDeser::new(map, None))
+      }
+      it => it.deserialize_any(visitor),
+    }
+  }
+
+  fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self {
+      Value::Seq(seq) => visitor.visit_seq(SeqValueDeser(seq.into_iter())),
+      oh_no => Err(DeError::invalid_type(oh_no.unexpected(), &visitor)),
+    }
+  }
+  fn deserialize_tuple<V>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_seq(visitor)
+  }
+  fn deserialize_tuple_struct<V>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self {
+      Value::Unit => visitor.visit_map(MapValueDeser::new(Vec::new(), None)),
+      Value::Map(map) => visitor.visit_map(MapValueDeser::new(map, None)),
+      oh_no => Err(DeError::invalid_type(oh_no.unexpected(), &visitor)),
+    }
+  }
+  fn deserialize_struct<V>(
+    self,
+    _name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self {
+      Value::Unit => {
+        visitor.visit_map(MapValueDeser::new(Vec::new(), Some(fields)))
+      }
+      Value::Map(map) => {
+        visitor.visit_map(MapValueDeser::new(map, Some(fields)))
+      }
+      oh_no => Err(DeError::invalid_type(oh_no.unexpected(), &visitor)),
+    }
+  }
+
+  fn deserialize_enum<V>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self.unwrap_single() {
+      Value::String(variant) => visitor.visit_enum(EnumValueDeser {
+        variant,
+        value: None,
+      }),
+      Value::Annotated(variant, value) => visitor.visit_enum(EnumValueDeser {
+        variant,
+        value: Some(*value),
+      }),
+      // `{"Variant": value}`, the way serializers usually write enums
+      Value::Map(mut map) if map.len() == 1 => {
+        let (variant, value) = map.pop().unwrap();
+        visitor.visit_enum(EnumValueDeser {
+          variant,
+          value: Some(value),
+        })
+      }
+      oh_no => Err(DeError::invalid_type(oh_no.unexpected(), &visitor)),
+    }
+  }
+
+  fn deserialize_ignored_any<V>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_unit()
+  }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for Value {
+  type Deserializer = Self;
+
+  fn into_deserializer(self) -> Self::Deserializer {
+    self
+  }
+}
+
+impl Serialize for Value {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      Value::Unit => serializer.serialize_unit(),
+      Value::Bool(b) => serializer.serialize_bool(*b),
+      Value::Int(it) => {
+        if let Ok(it) = i64::try_from(*it) {
+          serializer.serialize_i64(it)
+        } else if let Ok(it) = u64::try_from(*it) {
+          serializer.serialize_u64(it)
+        } else {
+          serializer.serialize_i128(*it)
+        }
+      }
+      Value::Float(f) => serializer.serialize_f64(*f),
+      Value::String(s) => serializer.serialize_str(s),
+      Value::Bytes(b) => serializer.serialize_bytes(b),
+      Value::Seq(seq) => serializer.collect_seq(seq),
+      Value::Map(map) => SerializeEntries(map).serialize(serializer),
+      Value::Annotated(ann, inner) if annotation::is_reserved(ann) => {
+        // Ints too big to be a `Value::Int` stay as strings
+        if let Value::String(s) = &**inner {
+          match annotation::parse_string_int(ann, s) {
+            Some(Ok(BigInt::Negative(it))) => {
+              return serializer.serialize_i128(it)
+            }
+            Some(Ok(BigInt::Positive(it))) => {
+              return serializer.serialize_u128(it)
+            }
+            _ => {}
+          }
+        }
+        inner.serialize(serializer)
+      }
+      Value::Annotated(ann, inner) => {
+        // Variant names aren't `'static`, so this can't be a newtype variant
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(ann, inner)?;
+        map.end()
+      }
+      Value::Node {
+        args,
+        props,
+        children,
+      } => {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("args", args)?;
+        map.serialize_entry("props", &SerializeEntries(props))?;
+        map.serialize_entry("children", &SerializeEntries(children))?;
+        map.end()
+      }
+    }
+  }
+}
+
+/// Serializes the entries of a `Value::Map` as a map
+struct SerializeEntries<'a>(&'a [(String, Value)]);
+
+impl Serialize for SerializeEntries<'_> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+  }
+}
+
+struct SeqValueDeser(std::vec::IntoIter<Value>);
+
+impl<'de> de::SeqAccess<'de> for SeqValueDeser {
+  type Error = DeError;
+
+  fn next_element_seed<T>(
+    &mut self,
+    seed: T,
+  ) -> Result<Option<T::Value>, Self::Error>
+  where
+    T: de::DeserializeSeed<'de>,
+  {
+    match self.0.next() {
+      Some(it) => seed.deserialize(it).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.0.len())
+  }
+}
+
+struct MapValueDeser {
+  entries: std::vec::IntoIter<(String, Value)>,
+  /// For structs, keys that aren't already one of these get snekified
+  fields: Option<&'static [&'static str]>,
+
+  value: Option<Value>,
+}
+
+impl MapValueDeser {
+  fn new(
+    entries: Vec<(String, Value)>,
+    fields: Option<&'static [&'static str]>,
+  ) -> Self {
+    Self {
+      entries: entries.into_iter(),
+      fields,
+      value: None,
+    }
+  }
+}
+
+impl<'de> de::MapAccess<'de> for MapValueDeser {
+  type Error = DeError;
+
+  fn next_key_seed<K>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>, Self::Error>
+  where
+    K: de::DeserializeSeed<'de>,
+  {
+    if self.value.is_some() {
+      return Err(DeError::custom("map visitor requested two keys in a row"));
+    }
+    let Some((key, value)) = self.entries.next() else {
+      return Ok(None);
+    };
+    self.value = Some(value);
+    let key = match self.fields {
+      Some(fields) if !fields.contains(&key.as_str()) => key.to_snek_case(),
+      _ => key,
+    };
+    seed.deserialize(KeyDeser(Cow::Owned(key))).map(Some)
+  }
+
+  fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+  where
+    V: de::DeserializeSeed<'de>,
+  {
+    match self.value.take() {
+      Some(value) => seed.deserialize(value),
+      None => Err(DeError::custom(
+        "map visitor requested a value without a key",
+      )),
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.entries.len())
+  }
+}
+
+/// Deserializes `"variant"` into a unit enum, or `(variant)value` into
+/// anything else
+struct EnumValueDeser {
+  variant: String,
+  value: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumValueDeser {
+  type Error = DeError;
+  type Variant = VariantValueDeser;
+
+  fn variant_seed<V>(
+    self,
+    seed: V,
+  ) -> Result<(V::Value, Self::Variant), Self::Error>
+  where
+    V: de::DeserializeSeed<'de>,
+  {
+    let variant = self.variant.into_deserializer();
+    seed
+      .deserialize(variant)
+      .map(|v| (v, VariantValueDeser(self.value)))
+  }
+}
+
+struct VariantValueDeser(Option<Value>);
+
+impl<'de> de::VariantAccess<'de> for VariantValueDeser {
+  type Error = DeError;
+
+  fn unit_variant(self) -> Result<(), Self::Error> {
+    match self.0 {
+      None => Ok(()),
+      Some(value) => Err(DeError::invalid_type(
+        value.unexpected(),
+        &"unannotated string",
+      )),
+    }
+  }
+
+  fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+  where
+    T: de::DeserializeSeed<'de>,
+  {
+    match self.0 {
+      Some(value) => seed.deserialize(value),
+      None => Err(DeError::invalid_type(
+        Unexpected::UnitVariant,
+        &"annotated value",
+      )),
+    }
+  }
+
+  fn tuple_variant<V>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Some(value) => value.deserialize_seq(visitor),
+      None => Err(DeError::invalid_type(Unexpected::UnitVariant, &visitor)),
+    }
+  }
+
+  fn struct_variant<V>(
+    self,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    match self.0 {
+      Some(value) => value.deserialize_struct("", &[], visitor),
+      None => Err(DeError::invalid_type(Unexpected::UnitVariant, &visitor)),
+    }
+  }
+}
@@ -0,0 +1,529 @@
+//! [XML-in-KDL](https://github.com/kdl-org/kdl/blob/main/XML-IN-KDL.md),
+//! the KDL project's way of writing XML as KDL.
+//!
+//! Elements are read the way quick-xml's serde support reads XML, so types
+//! written for it work here too:
+//!
+//! - Node names are element names, and child elements are fields. Repeated
+//!   elements with the same name deserialize as a sequence.
+//! - Properties are attributes, and show up as fields named `@name`.
+//! - String arguments, and string arguments of `-` children, are the text
+//!   content. An element with only text can be deserialized straight into
+//!   a string or number, and otherwise the text is the field `$text`.
+//!
+//! Comments, processing instructions and doctypes (nodes whose names start
+//! with `!` or `?`) are skipped.
+//!
+//! Everything in XML is text, so of the [`DeOptions`] only interpolation
+//! applies here. Annotations are ignored, so there's nothing for annotation
+//! handlers to do, and numbers are parsed from their text instead of going
+//! through the numeric policy.
+
+use std::borrow::Cow;
+
+use kdl::{KdlDocument, KdlNode, KdlValue};
+use serde::{
+  de::{self, Error, IntoDeserializer, Visitor},
+  Deserialize,
+};
+
+use crate::{interpolate::interpolate, key::KeyDeser, DeError, DeOptions};
+
+/// Deserialize an XiK element.
+pub fn deserialize_node<'de, T: Deserialize<'de>>(
+  node: &'de KdlNode,
+) -> Result<T, DeError> {
+  T::deserialize(XikDeser::new(node))
+}
+
+/// Deserialize an XiK element with the given options.
+pub fn deserialize_node_with<'de, T: Deserialize<'de>>(
+  node: &'de KdlNode,
+  options: &'de DeOptions,
+) -> Result<T, DeError> {
+  T::deserialize(XikDeser::with_options(node, options))
+}
+
+/// Deserialize the root element of an XiK document.
+pub fn deserialize_document<'de, T: Deserialize<'de>>(
+  doc: &'de KdlDocument,
+) -> Result<T, DeError> {
+  deserialize_document_with(doc, DeOptions::default_ref())
+}
+
+/// Deserialize the root element of an XiK document with the given options.
+pub fn deserialize_document_with<'de, T: Deserialize<'de>>(
+  doc: &'de KdlDocument,
+  options: &'de DeOptions,
+) -> Result<T, DeError> {
+  let root = doc
+    .nodes()
+    .iter()
+    .find(|node| is_element(node))
+    .ok_or_else(|| DeError::custom("XiK document has no root element"))?;
+  deserialize_node_with(root, options)
+}
+
+/// Nodes named `-` are text and `!`/`?` nodes are comments and such
+fn is_element(node: &KdlNode) -> bool {
+  !matches!(node.name().value().chars().next(), Some('-' | '!' | '?'))
+}
+
+fn is_text(node: &KdlNode) -> bool {
+  node.name().value() == "-"
+}
+
+fn text_of<'a>(
+  value: &'a KdlValue,
+  options: &DeOptions,
+) -> Result<Cow<'a, str>, DeError> {
+  Ok(match value {
+    KdlValue::String(s) => interpolate(s, options)?,
+    KdlValue::Integer(it) => Cow::Owned(it.to_string()),
+    KdlValue::Float(f) => Cow::Owned(f.to_string()),
+    KdlValue::Bool(b) => Cow::Owned(b.to_string()),
+    KdlValue::Null => Cow::Borrowed(""),
+  })
+}
+
+/// Deserializer for an XiK element
+#[derive(Debug, Clone, Copy)]
+pub struct XikDeser<'de> {
+  node: &'de KdlNode,
+  options: &'de DeOptions,
+}
+
+impl<'de> XikDeser<'de> {
+  pub fn new(node: &'de KdlNode) -> Self {
+    Self::with_options(node, DeOptions::default_ref())
+  }
+
+  pub fn with_options(node: &'de KdlNode, options: &'de DeOptions) -> Self {
+    Self { node, options }
+  }
+
+  fn kids(&self) -> &'de [KdlNode] {
+    self.node.children().map_or(&[][..], |kids| kids.nodes())
+  }
+
+  /// All the text content, joined together
+  fn text(&self) -> Result<Cow<'de, str>, DeError> {
+    let args = self
+      .node
+      .entries()
+      .iter()
+      .filter(|entry| entry.name().is_none())
+      .map(|entry| entry.value());
+    let text_kids = self
+      .kids()
+      .iter()
+      .filter(|kid| is_text(kid))
+      .flat_map(|kid| kid.entries().iter().map(|entry| entry.value()));
+    let mut pieces = args
+      .chain(text_kids)
+      .map(|value| text_of(value, self.options));
+    let Some(first) = pieces.next() else {
+      return Ok(Cow::Borrowed(""));
+    };
+    pieces.try_fold(first?, |acc, it| Ok(Cow::Owned(acc.into_owned() + &it?)))
+  }
+
+  fn has_attributes(&self) -> bool {
+    self.node.entries().iter().any(|entry| entry.name().is_some())
+  }
+
+  fn has_elements(&self) -> bool {
+    self.kids().iter().any(is_element)
+  }
+
+  fn text_deser(&self) -> Result<KeyDeser<'de>, DeError> {
+    Ok(KeyDeser(self.text()?))
+  }
+
+  fn map(&self) -> Result<XikMapDeser<'de>, DeError> {
+    let attributes = self
+      .node
+      .entries()
+      .iter()
+      .filter_map(|entry| {
+        let name = entry.name()?;
+        let text = text_of(entry.value(), self.options);
+        Some(text.map(|text| (format!("@{}", name.value()), text)))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let text = self.text()?;
+    let text = (!text.is_empty()).then_some(text);
+
+    // Group repeated elements, in the order each name first shows up
+    let mut elements: Vec<(&'de str, Vec<&'de KdlNode>)> = Vec::new();
+    for kid in self.kids().iter().filter(|kid| is_element(kid)) {
+      let name = kid.name().value();
+      match elements.iter_mut().find(|(it, _)| *it == name) {
+        Some((_, group)) => group.push(kid),
+        None => elements.push((name, vec![kid])),
+      }
+    }
+
+    Ok(XikMapDeser {
+      attributes: attributes.into_iter(),
+      text,
+      elements: elements.into_iter(),
+      value: None,
+      options: self.options,
+    })
+  }
+}
+
+/// Forward scalars to another deserializer
+macro_rules! forward_scalars {
+  (@ $self:ident => $deser:expr, $ty:ident) => {
+    paste::paste! {
+      fn [< deserialize_ $ty >]<V>($self, visitor: V) -> Result<V::Value, Self::Error>
+      where
+        V: Visitor<'de>,
+      {
+        $deser.[< deserialize_ $ty >](visitor)
+      }
+    }
+  };
+  ( $self:ident => $deser:expr; $($ty:ident)* ) => {
+    $(
+      forward_scalars!(@ $self => $deser, $ty);
+    )*
+  };
+}
+
+impl<'de> de::Deserializer<'de> for XikDeser<'de> {
+  type Error = DeError;
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    if self.has_attributes() || self.has_elements() {
+      visitor.visit_map(self.map()?)
+    } else {
+      self.text_deser()?.deserialize_any(visitor)
+    }
+  }
+
+  // Text content only
+  forward_scalars! {
+    self => self.text_deser()?;
+    u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 bool char
+    str string bytes byte_buf
+  }
+
+  fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    // It's here, so it's something
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_unit()
+  }
+  fn deserialize_unit_struct<V>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_unit(visitor)
+  }
+
+  fn deserialize_newtype_struct<V>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    // An element that shows up once is a sequence of one
+    XikGroupDeser(vec![self.node], self.options).deserialize_seq(visitor)
+  }
+  fn deserialize_tuple<V>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_seq(visitor)
+  }
+  fn deserialize_tuple_struct<V>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_map(self.map()?)
+  }
+  fn deserialize_struct<V>(
+    self,
+    _name: &'static str,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_enum<V>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    // Unit variants from the text content
+    self.text_deser()?.deserialize_enum(name, variants, visitor)
+  }
+
+  fn deserialize_identifier<V>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_str(visitor)
+  }
+
+  fn deserialize_ignored_any<V>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_unit()
+  }
+}
+
+/// Attributes, then the text, then the elements
+struct XikMapDeser<'de> {
+  attributes: std::vec::IntoIter<(String, Cow<'de, str>)>,
+  text: Option<Cow<'de, str>>,
+  elements: std::vec::IntoIter<(&'de str, Vec<&'de KdlNode>)>,
+
+  value: Option<XikMapDeserVal<'de>>,
+  options: &'de DeOptions,
+}
+
+enum XikMapDeserVal<'de> {
+  Text(Cow<'de, str>),
+  Elements(Vec<&'de KdlNode>),
+}
+
+impl<'de> de::MapAccess<'de> for XikMapDeser<'de> {
+  type Error = DeError;
+
+  fn next_key_seed<K>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>, Self::Error>
+  where
+    K: de::DeserializeSeed<'de>,
+  {
+    if self.value.is_some() {
+      return Err(DeError::custom("map visitor requested two keys in a row"));
+    }
+
+    let key = if let Some((key, val)) = self.attributes.next() {
+      self.value = Some(XikMapDeserVal::Text(val));
+      Cow::Owned(key)
+    } else if let Some(text) = self.text.take() {
+      self.value = Some(XikMapDeserVal::Text(text));
+      Cow::Borrowed("$text")
+    } else if let Some((key, group)) = self.elements.next() {
+      self.value = Some(XikMapDeserVal::Elements(group));
+      Cow::Borrowed(key)
+    } else {
+      return Ok(None);
+    };
+    seed.deserialize(KeyDeser(key)).map(Some)
+  }
+
+  fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+  where
+    V: de::DeserializeSeed<'de>,
+  {
+    match self.value.take() {
+      None => Err(DeError::custom(
+        "map visitor requested a value without a key",
+      )),
+      Some(XikMapDeserVal::Text(text)) => seed.deserialize(KeyDeser(text)),
+      Some(XikMapDeserVal::Elements(group)) => {
+        seed.deserialize(XikGroupDeser(group, self.options))
+      }
+    }
+  }
+}
+
+/// One or more elements with the same name
+struct XikGroupDeser<'de>(Vec<&'de KdlNode>, &'de DeOptions);
+
+impl<'de> XikGroupDeser<'de> {
+  fn single(self) -> Result<XikDeser<'de>, DeError> {
+    match self.0[..] {
+      [node] => Ok(XikDeser::with_options(node, self.1)),
+      _ => Err(DeError::custom(format!(
+        "element `{}` shows up more than once",
+        self.0[0].name().value()
+      ))),
+    }
+  }
+}
+
+impl<'de> de::Deserializer<'de> for XikGroupDeser<'de> {
+  type Error = DeError;
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.single()?.deserialize_any(visitor)
+  }
+
+  fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    let options = self.1;
+    let elements = self
+      .0
+      .into_iter()
+      .map(|node| XikDeser::with_options(node, options));
+    visitor.visit_seq(de::value::SeqDeserializer::new(elements))
+  }
+  fn deserialize_tuple<V>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_seq(visitor)
+  }
+  fn deserialize_tuple_struct<V>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_newtype_struct<V>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.single()?.deserialize_enum(name, variants, visitor)
+  }
+
+  fn deserialize_struct<V>(
+    self,
+    name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.single()?.deserialize_struct(name, fields, visitor)
+  }
+
+  fn deserialize_unit_struct<V>(
+    self,
+    name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.single()?.deserialize_unit_struct(name, visitor)
+  }
+
+  forward_scalars! {
+    self => self.single()?;
+    u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 bool char
+    str string bytes byte_buf identifier unit map
+  }
+
+  fn deserialize_ignored_any<V>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_unit()
+  }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for XikDeser<'de> {
+  type Deserializer = Self;
+
+  fn into_deserializer(self) -> Self::Deserializer {
+    self
+  }
+}
@@ -25,6 +25,7 @@ struct Holder {
 }
 
 #[test]
+#[cfg(feature = "v1")]
 fn to_serde() {
   let doc = r#"
     node1 an-enum="Variant1" {
@@ -45,7 +46,7 @@ fn to_serde() {
   let targets = node
     .nodes()
     .iter()
-    .map(|node| knurdy::deserialize_node::<Target>(node))
+    .map(knurdy::deserialize_node::<Target>)
     .collect::<Result<Vec<_>, _>>()
     .unwrap();
   assert_eq!(
@@ -90,7 +91,7 @@ fn bytes_and_chars_and_nulls() {
   let targets = node
     .nodes()
     .iter()
-    .map(|node| knurdy::deserialize_node::<Holder>(node))
+    .map(knurdy::deserialize_node::<Holder>)
     .collect::<Result<Vec<_>, _>>()
     .unwrap();
   assert_eq!(
@@ -111,3 +112,1933 @@ fn bytes_and_chars_and_nulls() {
     ]
   );
 }
+
+#[test]
+fn dynamic_values() {
+  use knurdy::Value;
+
+  let doc = r#"
+    node an-enum=(Variant2)"hello" {
+      a-kid 1 2 3
+    }
+    mixed 1 2 key="val" {
+      kid
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+
+  let value: Value = knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(
+    value,
+    Value::Map(vec![
+      (
+        "an-enum".into(),
+        Value::Annotated(
+          "Variant2".into(),
+          Box::new(Value::String("hello".into()))
+        )
+      ),
+      (
+        "a-kid".into(),
+        Value::Seq(vec![Value::Int(1), Value::Int(2), Value::Int(3)])
+      ),
+    ])
+  );
+  let target: Target = serde::Deserialize::deserialize(value).unwrap();
+  assert_eq!(
+    target,
+    Target {
+      an_enum: AnEnum::Variant2("hello".into()),
+      a_kid: Some(Kiddo(1, 2, 3.0)),
+    }
+  );
+
+  let value: Value = knurdy::deserialize_node(&doc.nodes()[1]).unwrap();
+  assert_eq!(
+    value,
+    Value::Node {
+      args: vec![Value::Int(1), Value::Int(2)],
+      props: vec![("key".into(), Value::String("val".into()))],
+      children: vec![("kid".into(), Value::Unit)],
+    }
+  );
+  let again: Value = serde::Deserialize::deserialize(value.clone()).unwrap();
+  assert_eq!(again, value);
+
+  // Properties aren't lost next to `-` children, or no children at all
+  let doc: KdlDocument = "node key=1 { - 1; }\nnode key=1 {}".parse().unwrap();
+  let value: Value = knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(
+    value,
+    Value::Map(vec![
+      ("key".into(), Value::Int(1)),
+      ("-".into(), Value::Seq(vec![Value::Int(1)])),
+    ])
+  );
+  let value: Value = knurdy::deserialize_node(&doc.nodes()[1]).unwrap();
+  assert_eq!(value, Value::Map(vec![("key".into(), Value::Int(1))]));
+}
+
+#[test]
+fn raw_nodes() {
+  use knurdy::{DeError, RawNode};
+
+  #[derive(Debug, Deserialize)]
+  struct Blueprint<'a> {
+    kind: String,
+    #[serde(borrow)]
+    data: RawNode<'a>,
+  }
+
+  let doc = r#"
+    bp kind="holder" {
+      data foo=0x41 bar="!" baz="@" quxx=0x42
+    }
+    bp kind="kiddo" {
+      data 1 2 3
+    }
+    bp kind="kiddo" data=1
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+
+  let bp: Blueprint = knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(bp.kind, "holder");
+  assert_eq!(bp.data.name(), "data");
+  assert_eq!(
+    bp.data.deserialize::<Holder>().unwrap(),
+    Holder {
+      foo: b'A',
+      bar: b'!',
+      baz: '@',
+      quxx: 'B',
+    }
+  );
+
+  let bp: Blueprint = knurdy::deserialize_node(&doc.nodes()[1]).unwrap();
+  assert_eq!(bp.data.deserialize::<Kiddo>().unwrap(), Kiddo(1, 2, 3.0));
+
+  // Properties aren't nodes
+  assert!(knurdy::deserialize_node::<Blueprint>(&doc.nodes()[2]).is_err());
+
+  // A foreign visitor asking for a raw node can't get one out
+  struct Thief;
+  impl<'de> serde::de::Visitor<'de> for Thief {
+    type Value = RawNode<'static>;
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+      f.write_str("anything")
+    }
+  }
+  let stolen = serde::Deserializer::deserialize_newtype_struct(
+    knurdy::KdlNodeDeser::new(&doc.nodes()[0]),
+    "$knurdy::private::RawNode",
+    Thief,
+  );
+  assert_eq!(stolen.unwrap_err(), DeError::RawNodeOutOfPlace);
+
+  // Flattening buffers the node, so there's nothing raw left to hand over
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  struct Flat<'a> {
+    #[serde(flatten, borrow)]
+    inner: Blueprint<'a>,
+  }
+  let err = knurdy::deserialize_node::<Flat>(&doc.nodes()[0]).unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    format!(
+      "the deserialize impl on the type reported an error: {}",
+      DeError::RawNodeOutOfPlace
+    )
+  );
+}
+
+#[test]
+fn seeded() {
+  use knurdy::RawNode;
+  use serde::de::{DeserializeSeed, Deserializer};
+
+  #[derive(Default)]
+  struct Interner(Vec<String>);
+  impl Interner {
+    fn intern(&mut self, s: String) -> usize {
+      match self.0.iter().position(|it| *it == s) {
+        Some(idx) => idx,
+        None => {
+          self.0.push(s);
+          self.0.len() - 1
+        }
+      }
+    }
+  }
+
+  struct InternSeed<'i>(&'i mut Interner);
+  impl<'de> DeserializeSeed<'de> for InternSeed<'_> {
+    type Value = usize;
+    fn deserialize<D: Deserializer<'de>>(
+      self,
+      deserializer: D,
+    ) -> Result<usize, D::Error> {
+      let s = String::deserialize(deserializer)?;
+      Ok(self.0.intern(s))
+    }
+  }
+
+  struct AssetsSeed<'i>(&'i mut Interner);
+  impl<'de> DeserializeSeed<'de> for AssetsSeed<'_> {
+    type Value = Vec<(String, usize)>;
+    fn deserialize<D: Deserializer<'de>>(
+      self,
+      deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+      // `RawNode::deserialize` is the inherent method
+      let raw = <RawNode as Deserialize>::deserialize(deserializer)?;
+      raw
+        .deserializer()
+        .children()
+        .map(|kid| {
+          let name = kid.name().to_owned();
+          let id = InternSeed(self.0).deserialize(kid)?;
+          Ok((name, id))
+        })
+        .collect::<Result<_, knurdy::DeError>>()
+        .map_err(serde::de::Error::custom)
+    }
+  }
+
+  let doc = r#"
+    assets {
+      sprite "hero.png"
+      sound "step.ogg"
+      icon "hero.png"
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let mut interner = Interner::default();
+  let assets = knurdy::deserialize_node_seed(
+    AssetsSeed(&mut interner),
+    &doc.nodes()[0],
+  )
+  .unwrap();
+  assert_eq!(
+    assets,
+    vec![
+      ("sprite".to_owned(), 0),
+      ("sound".to_owned(), 1),
+      ("icon".to_owned(), 0)
+    ]
+  );
+  assert_eq!(interner.0, vec!["hero.png", "step.ogg"]);
+
+  // The options make it through to the seed
+  let doc: KdlDocument =
+    r#"assets { sprite "${dir}/hero.png" }"#.parse().unwrap();
+  let options = knurdy::DeOptions::new()
+    .with_interpolation(true)
+    .with_var("dir", "art");
+  let assets = knurdy::deserialize_node_seed_with(
+    AssetsSeed(&mut interner),
+    &doc.nodes()[0],
+    &options,
+  )
+  .unwrap();
+  assert_eq!(assets, vec![("sprite".to_owned(), 2)]);
+  assert_eq!(interner.0[2], "art/hero.png");
+}
+
+#[test]
+fn reserved_annotations() {
+  use knurdy::DeError;
+  use std::net::Ipv4Addr;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Reserved {
+    small: u32,
+    addr: Ipv4Addr,
+    when: String,
+  }
+
+  let doc = r#"
+    ok small=(u8)200 addr=(ipv4)"10.0.0.1" when=(date-time)"2022-02-28T12:30:00Z"
+    too-big small=(u8)300 addr=(ipv4)"10.0.0.1" when=(date-time)"2022-02-28T12:30:00Z"
+    bad-addr small=(u8)1 addr=(ipv4)"10.0.0" when=(date-time)"2022-02-28T12:30:00Z"
+    bad-date small=(u8)1 addr=(ipv4)"10.0.0.1" when=(date-time)"2022-02-29T12:30:00Z"
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let results = doc
+    .nodes()
+    .iter()
+    .map(knurdy::deserialize_node::<Reserved>)
+    .collect::<Vec<_>>();
+
+  assert_eq!(
+    results[0],
+    Ok(Reserved {
+      small: 200,
+      addr: Ipv4Addr::new(10, 0, 0, 1),
+      when: "2022-02-28T12:30:00Z".into(),
+    })
+  );
+  for (res, ann) in results[1..].iter().zip(["u8", "ipv4", "date-time"]) {
+    match res {
+      Err(DeError::AnnotationMismatch { annotation, .. }) => {
+        assert_eq!(annotation, ann)
+      }
+      oh_no => panic!("expected a mismatch on {}, got {:?}", ann, oh_no),
+    }
+  }
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn uuid_annotations() {
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct HasId {
+    id: uuid::Uuid,
+  }
+
+  let doc = r#"
+    ok id=(uuid)"67e55044-10b1-426f-9247-bb680e5fe0c8"
+    bad id=(uuid)"67e55044-10b1-426f-9247"
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  assert_eq!(
+    knurdy::deserialize_node::<HasId>(&doc.nodes()[0]).unwrap(),
+    HasId {
+      id: uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8")
+    }
+  );
+  assert!(matches!(
+    knurdy::deserialize_node::<HasId>(&doc.nodes()[1]),
+    Err(knurdy::DeError::AnnotationMismatch { .. })
+  ));
+}
+
+#[test]
+fn custom_annotations() {
+  use knurdy::{DeError, DeOptions, Value};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Color(u8, u8, u8);
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Widget {
+    color: Color,
+    width: f64,
+    fade: f64,
+  }
+
+  let options = DeOptions::new()
+    .with_annotation("color", |v| {
+      let hex = v
+        .as_string()
+        .and_then(|s| s.strip_prefix('#'))
+        .filter(|s| s.len() == 6)
+        .ok_or_else(|| DeError::MismatchedType("bad color".into()))?;
+      let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+          .map(|c| Value::Int(c.into()))
+          .map_err(|e| DeError::MismatchedType(e.to_string()))
+      };
+      Ok(Value::Seq(vec![channel(0)?, channel(2)?, channel(4)?]))
+    })
+    // Pretend there are 2 pixels per unit
+    .with_annotation("px", |v| {
+      let units = v.as_integer().unwrap_or(0) as f64 / 2.0;
+      Ok(kdl::KdlValue::Float(units).into())
+    })
+    .with_annotation("duration", |v| {
+      v.as_string()
+        .and_then(|s| s.strip_suffix('s'))
+        .and_then(|s| s.parse().ok())
+        .map(Value::Float)
+        .ok_or_else(|| DeError::MismatchedType("bad duration".into()))
+    });
+
+  let doc = r##"
+    widget color=(color)"#ff8800" width=(px)12 {
+      fade (duration)"1.5s"
+    }
+    widget color=(color)"orange" width=(px)12 fade=1.0
+    "##;
+  let doc: KdlDocument = doc.parse().unwrap();
+  assert_eq!(
+    knurdy::deserialize_node_with::<Widget>(&doc.nodes()[0], &options),
+    Ok(Widget {
+      color: Color(0xff, 0x88, 0x00),
+      width: 6.0,
+      fade: 1.5,
+    })
+  );
+  assert!(
+    knurdy::deserialize_node_with::<Widget>(&doc.nodes()[1], &options)
+      .is_err()
+  );
+}
+
+#[test]
+fn byte_encodings() {
+  use serde_bytes::ByteBuf;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Blobs {
+    hex: ByteBuf,
+    url: ByteBuf,
+    b32: ByteBuf,
+    b85: ByteBuf,
+    plain: ByteBuf,
+    data: ByteBuf,
+  }
+
+  let doc = r#"
+    blobs hex=(hex)"DEADbeef" url=(base64url)"-_8" b32=(base32)"MZXW6===" {
+      b85 (base85)"Xk~0{Zv"
+      plain "hi"
+      data 1 2 3 255
+    }
+    bad hex=(hex)"abc"
+    too-big 1 2 256
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let blobs = knurdy::deserialize_node::<Blobs>(&doc.nodes()[0]).unwrap();
+  assert_eq!(
+    blobs,
+    Blobs {
+      hex: ByteBuf::from(vec![0xde, 0xad, 0xbe, 0xef]),
+      url: ByteBuf::from(vec![0xfb, 0xff]),
+      b32: ByteBuf::from(b"foo".to_vec()),
+      b85: ByteBuf::from(b"hello".to_vec()),
+      plain: ByteBuf::from(b"hi".to_vec()),
+      data: ByteBuf::from(vec![1, 2, 3, 255]),
+    }
+  );
+
+  // and through a `Value`
+  let value: knurdy::Value =
+    knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(serde::Deserialize::deserialize(value), Ok(blobs));
+
+  #[derive(Debug, Deserialize)]
+  struct JustHex {
+    #[allow(dead_code)]
+    hex: ByteBuf,
+  }
+  assert!(matches!(
+    knurdy::deserialize_node::<JustHex>(&doc.nodes()[1]),
+    Err(knurdy::DeError::BytesDecode { encoding: "hex", .. })
+  ));
+  assert!(knurdy::deserialize_node::<ByteBuf>(&doc.nodes()[2]).is_err());
+}
+
+#[test]
+fn big_integers() {
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Seeds {
+    hash: u64,
+    huge: u128,
+    tiny: i128,
+    small: u64,
+  }
+
+  let doc = r#"
+    seeds hash=(u64)"18446744073709551615" small=(u64)12 {
+      huge (u128)"0xffff_ffff_ffff_ffff_ffff_ffff_ffff_ffff"
+      tiny (i128)"-170141183460469231731687303715884105728"
+    }
+    too-big hash=(u64)"18446744073709551616"
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let seeds = Seeds {
+    hash: u64::MAX,
+    huge: u128::MAX,
+    tiny: i128::MIN,
+    small: 12,
+  };
+  assert_eq!(
+    knurdy::deserialize_node::<Seeds>(&doc.nodes()[0]).unwrap(),
+    seeds
+  );
+
+  let value: knurdy::Value =
+    knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(
+    value.get("hash"),
+    Some(&knurdy::Value::Annotated(
+      "u64".into(),
+      Box::new(knurdy::Value::Int(u64::MAX.into()))
+    ))
+  );
+  assert_eq!(serde::Deserialize::deserialize(value), Ok(seeds));
+
+  #[derive(Debug, Deserialize)]
+  struct JustHash {
+    #[allow(dead_code)]
+    hash: u64,
+  }
+  assert!(matches!(
+    knurdy::deserialize_node::<JustHash>(&doc.nodes()[1]),
+    Err(knurdy::DeError::AnnotationMismatch { .. })
+  ));
+}
+
+#[test]
+fn numeric_policies() {
+  use knurdy::{DeError, DeOptions, NumericPolicy};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Floaty {
+    single: f32,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Inty {
+    count: u32,
+  }
+
+  let doc = r#"
+    exact single=0.5
+    big-int single=16777217
+    fraction single=0.1
+    whole count=3.0
+    half count=3.5
+    negative count=-1
+    huge count=1e10
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let nodes = doc.nodes();
+  let strict = DeOptions::new().with_numeric_policy(NumericPolicy::Strict);
+  let integral =
+    DeOptions::new().with_numeric_policy(NumericPolicy::IntegralFloat);
+
+  assert_eq!(
+    knurdy::deserialize_node_with::<Floaty>(&nodes[0], &strict),
+    Ok(Floaty { single: 0.5 })
+  );
+  assert_eq!(
+    knurdy::deserialize_node::<Floaty>(&nodes[1]),
+    Ok(Floaty { single: 16777216.0 })
+  );
+  assert!(matches!(
+    knurdy::deserialize_node_with::<Floaty>(&nodes[1], &strict),
+    Err(DeError::LossyNumber { target: "f32", .. })
+  ));
+  assert!(knurdy::deserialize_node::<Floaty>(&nodes[2]).is_ok());
+  assert!(matches!(
+    knurdy::deserialize_node_with::<Floaty>(&nodes[2], &strict),
+    Err(DeError::LossyNumber { target: "f32", .. })
+  ));
+
+  assert!(matches!(
+    knurdy::deserialize_node::<Inty>(&nodes[3]),
+    Err(DeError::FloatToInt { target: "u32", .. })
+  ));
+  assert_eq!(
+    knurdy::deserialize_node_with::<Inty>(&nodes[3], &integral),
+    Ok(Inty { count: 3 })
+  );
+  assert!(matches!(
+    knurdy::deserialize_node_with::<Inty>(&nodes[4], &integral),
+    Err(DeError::NonIntegralFloat { target: "u32", .. })
+  ));
+
+  // Integers that don't fit
+  assert!(matches!(
+    knurdy::deserialize_node::<Inty>(&nodes[5]),
+    Err(DeError::IntSize(_))
+  ));
+  assert_eq!(
+    knurdy::deserialize_node_with::<Inty>(&nodes[5], &strict),
+    Err(DeError::LossyNumber {
+      value: "-1".to_owned(),
+      target: "u32"
+    })
+  );
+  assert_eq!(
+    knurdy::deserialize_node_with::<Inty>(&nodes[6], &integral),
+    Err(DeError::LossyNumber {
+      value: "10000000000".to_owned(),
+      target: "u32"
+    })
+  );
+}
+
+#[test]
+fn infinity_and_nan() {
+  #[derive(Debug, Deserialize)]
+  struct Range {
+    min: f64,
+    max: f32,
+    scale: f64,
+  }
+
+  let doc = r#"
+    range min=(f64)"-inf" max=(f32)"inf" scale=(f64)"nan"
+    bad min=(f64)"infinite" max=1 scale=1
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let range = knurdy::deserialize_node::<Range>(&doc.nodes()[0]).unwrap();
+  assert_eq!(range.min, f64::NEG_INFINITY);
+  assert_eq!(range.max, f32::INFINITY);
+  assert!(range.scale.is_nan());
+
+  let value: knurdy::Value =
+    knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(
+    value.get("max"),
+    Some(&knurdy::Value::Annotated(
+      "f32".into(),
+      Box::new(knurdy::Value::Float(f64::INFINITY))
+    ))
+  );
+
+  assert!(matches!(
+    knurdy::deserialize_node::<Range>(&doc.nodes()[1]),
+    Err(knurdy::DeError::AnnotationMismatch { .. })
+  ));
+}
+
+#[test]
+#[cfg(feature = "v1")]
+fn kdl_v2() {
+  use knurdy::KdlVersion;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Body {
+    name: String,
+    enabled: bool,
+    parent: Option<String>,
+    mass: u64,
+    drag: f64,
+    notes: String,
+  }
+
+  let v2 = r##"
+    body name=#"raw "quoted" name"# enabled=#true parent=#null {
+      mass 18446744073709551615
+      drag #inf
+      notes """
+        first line
+          second line
+        """
+    }
+    "##;
+  let v1 = r##"
+    body name=r#"raw "quoted" name"# enabled=true parent=null {
+      mass (u64)"18446744073709551615"
+      drag (f64)"inf"
+      notes "first line\n  second line"
+    }
+    "##;
+  let expected = Body {
+    name: r#"raw "quoted" name"#.into(),
+    enabled: true,
+    parent: None,
+    mass: u64::MAX,
+    drag: f64::INFINITY,
+    notes: "first line\n  second line".into(),
+  };
+
+  for (text, version) in [(v2, KdlVersion::V2), (v1, KdlVersion::V1)] {
+    for version in [version, KdlVersion::Detect] {
+      let doc = knurdy::parse_document(text, version).unwrap();
+      assert_eq!(
+        knurdy::deserialize_node::<Body>(&doc.nodes()[0]).unwrap(),
+        expected
+      );
+    }
+  }
+  assert!(knurdy::parse_document(v1, KdlVersion::V2).is_err());
+  assert!(knurdy::parse_document(v2, KdlVersion::V1).is_err());
+}
+
+#[test]
+fn map_keys() {
+  use std::collections::{BTreeMap, HashMap};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Level {
+    size: u32,
+  }
+  #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+  enum Direction {
+    North,
+    South,
+  }
+
+  let doc = r#"
+    levels {
+      "1" size=10
+      "2" size=20
+    }
+    exits North="hall" South="cellar"
+    toggles "true"=1 "false"=0
+    bad-levels {
+      one size=1
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let nodes = doc.nodes();
+
+  let levels: HashMap<u32, Level> =
+    knurdy::deserialize_node(&nodes[0]).unwrap();
+  assert_eq!(levels[&1], Level { size: 10 });
+  assert_eq!(levels[&2], Level { size: 20 });
+
+  let exits: BTreeMap<Direction, String> =
+    knurdy::deserialize_node(&nodes[1]).unwrap();
+  assert_eq!(
+    exits.into_iter().collect::<Vec<_>>(),
+    vec![
+      (Direction::North, "hall".to_owned()),
+      (Direction::South, "cellar".to_owned())
+    ]
+  );
+
+  let toggles: HashMap<bool, u8> =
+    knurdy::deserialize_node(&nodes[2]).unwrap();
+  assert_eq!(toggles[&true], 1);
+
+  // and through a `Value`
+  let value: knurdy::Value = knurdy::deserialize_node(&nodes[0]).unwrap();
+  let levels: BTreeMap<u32, Level> =
+    serde::Deserialize::deserialize(value).unwrap();
+  assert_eq!(levels.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+
+  assert!(
+    knurdy::deserialize_node::<HashMap<u32, Level>>(&nodes[3]).is_err()
+  );
+}
+
+#[test]
+fn flag_nodes() {
+  use knurdy::{DeError, DeOptions};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Item {
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    unique: bool,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  enum Command {
+    Quit,
+    Say(String),
+    FullScreen,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Binding {
+    command: Command,
+  }
+
+  let doc = r#"
+    item {
+      hidden
+    }
+    Quit
+    command (Say)"hi"
+    full-screen
+    binding {
+      command
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let nodes = doc.nodes();
+  let options = DeOptions::new().with_flag_nodes(true);
+
+  assert_eq!(
+    knurdy::deserialize_node_with::<Item>(&nodes[0], &options),
+    Ok(Item {
+      hidden: true,
+      unique: false,
+    })
+  );
+  assert!(knurdy::deserialize_node::<Item>(&nodes[0]).is_err());
+
+  assert_eq!(
+    knurdy::deserialize_node_with::<Command>(&nodes[1], &options),
+    Ok(Command::Quit)
+  );
+  assert!(knurdy::deserialize_node::<Command>(&nodes[1]).is_err());
+  assert_eq!(
+    knurdy::deserialize_node_with::<Command>(&nodes[2], &options),
+    Ok(Command::Say("hi".into()))
+  );
+  assert_eq!(
+    knurdy::deserialize_node_with::<Command>(&nodes[3], &options),
+    Ok(Command::FullScreen)
+  );
+
+  // A field's node is named after the field, not a variant
+  let err = knurdy::deserialize_node_with::<Binding>(&nodes[4], &options)
+    .unwrap_err();
+  assert_eq!(
+    err,
+    DeError::BareEnumNode {
+      node: "command".into(),
+      enum_name: "Command",
+    }
+  );
+  assert_eq!(
+    err.to_string(),
+    "bare node `command` doesn't name a variant of `Command`, so give the \
+     variant as an argument"
+  );
+}
+
+#[test]
+#[cfg(feature = "v1")]
+fn options() {
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Patch {
+    #[serde(default, deserialize_with = "knurdy::double_option")]
+    color: Option<Option<String>>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    tag: Option<()>,
+  }
+
+  let doc = r#"
+    patch {
+      color null
+      name
+    }
+    patch color="red" name=null {
+      tag
+    }
+    patch {}
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let patches = doc
+    .nodes()
+    .iter()
+    .map(knurdy::deserialize_node::<Patch>)
+    .collect::<Result<Vec<_>, _>>()
+    .unwrap();
+  assert_eq!(
+    patches,
+    vec![
+      Patch {
+        color: Some(None),
+        name: None,
+        tag: None,
+      },
+      Patch {
+        color: Some(Some("red".into())),
+        name: None,
+        tag: None,
+      },
+      Patch {
+        color: None,
+        name: None,
+        tag: None,
+      },
+    ]
+  );
+
+  // a null argument with children isn't null
+  let doc: KdlDocument = "kid #null { inner 1; }".parse().unwrap();
+  let kid: Option<knurdy::Value> =
+    knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert!(kid.is_some());
+}
+
+#[test]
+fn transcoding() {
+  use serde_json::json;
+
+  let doc = r#"
+    ship name="Tern" crew=(u64)"18446744073709551615" {
+      hull (Circle)5
+      cargo {
+        - "fish"
+        - "salt"
+      }
+      drive 3 max=(f64)"inf"
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+
+  let mut out = Vec::new();
+  knurdy::transcode(&doc.nodes()[0], &mut serde_json::Serializer::new(&mut out))
+    .unwrap();
+  let out: serde_json::Value = serde_json::from_slice(&out).unwrap();
+  assert_eq!(
+    out,
+    json!({
+      "name": "Tern",
+      "crew": u64::MAX,
+      "hull": { "Circle": 5 },
+      "cargo": ["fish", "salt"],
+      "drive": {
+        "args": [3],
+        "props": { "max": null },
+        "children": {},
+      },
+    })
+  );
+
+  #[cfg(feature = "serde_json")]
+  assert_eq!(
+    knurdy::to_json_value(&doc).unwrap(),
+    json!({ "ship": out })
+  );
+
+  // Siblings with the same name all make it out
+  let doc: KdlDocument = r#"
+    item 1
+    item 2
+    crate label="a" {
+      label "b"
+      slot "x"
+      slot "y"
+    }
+    "#
+  .parse()
+  .unwrap();
+  let mut out = Vec::new();
+  knurdy::transcode_document(&doc, &mut serde_json::Serializer::new(&mut out))
+    .unwrap();
+  let out: serde_json::Value = serde_json::from_slice(&out).unwrap();
+  assert_eq!(
+    out,
+    json!({
+      "item": [1, 2],
+      "crate": { "label": ["a", "b"], "slot": ["x", "y"] },
+    })
+  );
+}
+
+#[test]
+fn jik() {
+  use serde_json::json;
+
+  let doc = r#"
+    - {
+      name "knurdy"
+      tags "kdl" "serde"
+      (array)empty-list
+      (array)one-item 1
+      (object)nothing
+      nested {
+        - 1
+        - {
+          deep #true
+        }
+        - #null
+      }
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let json: serde_json::Value =
+    knurdy::jik::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(
+    json,
+    json!({
+      "name": "knurdy",
+      "tags": ["kdl", "serde"],
+      "empty-list": [],
+      "one-item": [1],
+      "nothing": {},
+      "nested": [1, { "deep": true }, null],
+    })
+  );
+
+  // Round-trip through text
+  let node = knurdy::jik::to_node(&json).unwrap();
+  let doc: KdlDocument = node.to_string().parse().unwrap();
+  let back: serde_json::Value =
+    knurdy::jik::deserialize_node(&doc.nodes()[0]).unwrap();
+  assert_eq!(back, json);
+
+  #[derive(Debug, PartialEq, serde::Serialize, Deserialize)]
+  #[serde(rename_all = "camelCase")]
+  struct Config {
+    max_speed: f64,
+    modes: Vec<Mode>,
+  }
+  #[derive(Debug, PartialEq, serde::Serialize, Deserialize)]
+  enum Mode {
+    Idle,
+    Cruise(u32),
+  }
+  let config = Config {
+    max_speed: f64::INFINITY,
+    modes: vec![Mode::Idle, Mode::Cruise(3)],
+  };
+  let node = knurdy::jik::to_node(&config).unwrap();
+  let doc: KdlDocument = node.to_string().parse().unwrap();
+  assert_eq!(knurdy::jik::deserialize_node(&doc.nodes()[0]), Ok(config));
+
+  let doc: KdlDocument = "- 1 a=2".parse().unwrap();
+  assert_eq!(
+    knurdy::jik::deserialize_node::<serde_json::Value>(&doc.nodes()[0]),
+    Err(knurdy::DeError::InvalidJik("objects can't have arguments"))
+  );
+}
+
+#[test]
+fn xik() {
+  let doc = r#"
+    !doctype "html"
+    library name="City" {
+      book id="1" {
+        title "Dune"
+        pages "412"
+      }
+      book id="2" {
+        title {
+          - "The Left Hand "
+          - "of Darkness"
+        }
+        pages 304
+        format "paperback"
+      }
+      note lang="en" "Closed on Sundays"
+    }
+    "#;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Library {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "book")]
+    books: Vec<Book>,
+    note: Note,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Book {
+    #[serde(rename = "@id")]
+    id: u32,
+    title: String,
+    pages: u32,
+    format: Option<Format>,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  #[serde(rename_all = "lowercase")]
+  enum Format {
+    Hardcover,
+    Paperback,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Note {
+    #[serde(rename = "@lang")]
+    lang: String,
+    #[serde(rename = "$text")]
+    text: String,
+  }
+
+  let doc: KdlDocument = doc.parse().unwrap();
+  let library: Library = knurdy::xik::deserialize_document(&doc).unwrap();
+  assert_eq!(
+    library,
+    Library {
+      name: "City".to_owned(),
+      books: vec![
+        Book {
+          id: 1,
+          title: "Dune".to_owned(),
+          pages: 412,
+          format: None,
+        },
+        Book {
+          id: 2,
+          title: "The Left Hand of Darkness".to_owned(),
+          pages: 304,
+          format: Some(Format::Paperback),
+        },
+      ],
+      note: Note {
+        lang: "en".to_owned(),
+        text: "Closed on Sundays".to_owned(),
+      },
+    }
+  );
+
+  #[derive(Debug, Deserialize)]
+  #[allow(dead_code)]
+  struct OneBook {
+    book: Book,
+  }
+  let err = knurdy::xik::deserialize_node::<OneBook>(&doc.nodes()[1]);
+  assert!(err.is_err());
+
+  // Interpolation is the only option that applies to text
+  let doc: KdlDocument =
+    r#"note lang="${lang}" "Closed on ${day}s""#.parse().unwrap();
+  let options = knurdy::DeOptions::new()
+    .with_interpolation(true)
+    .with_var("lang", "en")
+    .with_var("day", "Sunday");
+  assert_eq!(
+    knurdy::xik::deserialize_node_with::<Note>(&doc.nodes()[0], &options),
+    Ok(Note {
+      lang: "en".to_owned(),
+      text: "Closed on Sundays".to_owned(),
+    })
+  );
+}
+
+#[test]
+fn schema() {
+  use knurdy::schema::{Schema, Violation};
+
+  let schema = Schema::parse(
+    r#"
+    document {
+      node "package" {
+        min 1
+        max 1
+        prop "name" {
+          required #true
+          type "string"
+          min-length 1
+        }
+        children {
+          node "version" {
+            max 1
+            value {
+              min 1
+              max 1
+              type "string"
+            }
+          }
+          node "dependencies" {
+            children {
+              node ref="[id=\"dependency\"]"
+            }
+          }
+        }
+      }
+      definitions {
+        node id="dependency" {
+          value {
+            max 1
+            type "string"
+          }
+          prop "optional" {
+            type "boolean"
+          }
+          prop "level" {
+            ">=" 0
+            "<" 4
+          }
+        }
+      }
+    }
+    "#,
+  )
+  .unwrap();
+
+  let doc: KdlDocument = r#"
+    package name="knurdy" {
+      version "0.2.0"
+      dependencies {
+        kdl "6.7.1"
+        serde "1.0" optional=#false level=3
+      }
+    }
+    "#
+  .parse()
+  .unwrap();
+  assert_eq!(schema.validate(&doc), Ok(()));
+
+  let doc: KdlDocument = r#"
+    package color="red" {
+      version 2
+      version "2.0" "3.0"
+      dependencies {
+        kdl optional="yes"
+        serde level=4
+      }
+    }
+    extra
+    "#
+  .parse()
+  .unwrap();
+  let violation = |path: &str, message: &str| Violation {
+    path: path.to_owned(),
+    message: message.to_owned(),
+  };
+  assert_eq!(
+    schema.validate(&doc),
+    Err(vec![
+      violation("/package", "missing required property `name`"),
+      violation("/package", "unexpected property `color`"),
+      violation(
+        "/package/version[0]",
+        "argument 0: expected a string, found 2"
+      ),
+      violation("/package/version[1]", "expected at most 1 arguments, found 2"),
+      violation(
+        "/package/dependencies/kdl",
+        "property `optional`: expected a boolean, found \"yes\""
+      ),
+      violation(
+        "/package/dependencies/serde",
+        "property `level`: 4 isn't less than 4"
+      ),
+      violation("/package", "expected at most 1 `version` nodes, found 2"),
+      violation("/extra", "unexpected node `extra`"),
+    ])
+  );
+
+  assert!(matches!(
+    Schema::parse("document { node ref=\"[id=\\\"nope\\\"]\"; }"),
+    Err(knurdy::schema::SchemaError::Invalid { .. })
+  ));
+}
+
+#[test]
+fn schema_generation() {
+  use std::collections::HashMap;
+
+  use knurdy::schema::Schema;
+
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  struct Blueprint {
+    name: String,
+    health: u32,
+    kind: Kind,
+    speed: Option<f64>,
+    tags: Vec<String>,
+    parts: Vec<Part>,
+    stats: HashMap<String, i32>,
+    #[serde(default)]
+    notes: Vec<String>,
+  }
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  enum Kind {
+    Ship,
+    Station,
+  }
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  struct Part {
+    id: char,
+    mount: Mount,
+    subparts: Vec<Part>,
+  }
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  enum Mount {
+    Fixed(u8),
+    Turret(f32),
+  }
+
+  let schema = knurdy::schema::generate::<Blueprint>("blueprint").unwrap();
+  let schema = Schema::new(&schema).unwrap();
+
+  let doc = r#"
+    blueprint name="Tug" kind="Ship" {
+      health 40
+      tags "small" "slow"
+      parts {
+        - id="a" mount=(Fixed)1 {
+          subparts {
+            - id="b" mount=(Turret)0.5 {
+              subparts {}
+            }
+          }
+        }
+      }
+      stats hull=3 crew=2
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  assert_eq!(schema.validate(&doc), Ok(()));
+  let _: Blueprint = knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+
+  let doc = r#"
+    blueprint name="Tug" health=-1 kind="Rock" {
+      parts {
+        - id="ab" mount=(Glued)1 {
+          subparts {}
+        }
+      }
+      stats
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let paths: Vec<_> = schema
+    .validate(&doc)
+    .unwrap_err()
+    .into_iter()
+    .map(|it| it.path)
+    .collect();
+  assert_eq!(
+    paths,
+    [
+      "/blueprint",
+      "/blueprint",
+      "/blueprint/parts/-",
+      "/blueprint/parts/-",
+      "/blueprint",
+    ]
+  );
+
+  #[cfg(feature = "serde_json")]
+  {
+    let json = knurdy::schema::json_schema::<Blueprint>().unwrap();
+    assert_eq!(json["$ref"], "#/$defs/Blueprint");
+    assert_eq!(json["$defs"]["Kind"]["oneOf"][1]["const"], "Station");
+    assert_eq!(
+      json["$defs"]["Mount"]["oneOf"][1]["properties"]["Turret"]["type"],
+      "number"
+    );
+
+    // Enough of JSON Schema for what `json_schema` writes
+    fn valid(
+      schema: &serde_json::Value,
+      value: &serde_json::Value,
+      root: &serde_json::Value,
+    ) -> bool {
+      use serde_json::Value as J;
+      let all = |items: &J, f: &dyn Fn(&J) -> bool| {
+        items.as_array().unwrap().iter().filter(|it| f(it)).count()
+      };
+      if let Some(J::String(path)) = schema.get("$ref") {
+        let def = &root["$defs"][path.trim_start_matches("#/$defs/")];
+        return valid(def, value, root);
+      }
+      if let Some(any) = schema.get("anyOf") {
+        return all(any, &|it| valid(it, value, root)) >= 1;
+      }
+      if let Some(one) = schema.get("oneOf") {
+        return all(one, &|it| valid(it, value, root)) == 1;
+      }
+      if let Some(c) = schema.get("const") {
+        return c == value;
+      }
+      let ty_ok = match schema.get("type").and_then(J::as_str) {
+        None => true,
+        Some("null") => value.is_null(),
+        Some("boolean") => value.is_boolean(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("string") => value.is_string(),
+        Some("array") => value.is_array(),
+        Some("object") => value.is_object(),
+        Some(other) => panic!("unknown type {other}"),
+      };
+      if !ty_ok {
+        return false;
+      }
+      if let (Some(min), Some(n)) = (schema.get("minimum"), value.as_f64()) {
+        if n < min.as_f64().unwrap() {
+          return false;
+        }
+      }
+      if let Some(items) = value.as_array() {
+        if let Some(each) = schema.get("items") {
+          if !items.iter().all(|it| valid(each, it, root)) {
+            return false;
+          }
+        }
+      }
+      if let Some(map) = value.as_object() {
+        let props = schema.get("properties");
+        for (key, it) in map {
+          let ok = match props.and_then(|p| p.get(key)) {
+            Some(prop) => valid(prop, it, root),
+            None => match schema.get("additionalProperties") {
+              Some(J::Bool(allowed)) => *allowed,
+              Some(extra) => valid(extra, it, root),
+              None => true,
+            },
+          };
+          if !ok {
+            return false;
+          }
+        }
+        if let Some(required) = schema.get("required") {
+          let missing = |it: &J| !map.contains_key(it.as_str().unwrap());
+          if all(required, &missing) > 0 {
+            return false;
+          }
+        }
+      }
+      true
+    }
+
+    // What `serde_json` reads, without the defaulted `notes`
+    let value = serde_json::json!({
+      "name": "Tug",
+      "health": 40,
+      "kind": "Ship",
+      "speed": null,
+      "tags": ["small", "slow"],
+      "parts": [{ "id": "a", "mount": { "Fixed": 1 }, "subparts": [] }],
+      "stats": { "hull": 3 },
+    });
+    assert!(valid(&json, &value, &json));
+    let _: Blueprint = serde_json::from_value(value).unwrap();
+
+    // Not what `to_json_value` writes, which has one tag as a string
+    let doc: KdlDocument = r#"
+      blueprint name="Tug" health=40 kind="Ship" {
+        tags "small"
+        parts {}
+        stats hull=3
+      }
+      "#
+    .parse()
+    .unwrap();
+    let written = knurdy::to_json_value(&doc).unwrap();
+    assert!(!valid(&json, &written["blueprint"], &json));
+  }
+}
+
+#[test]
+fn examples() {
+  use std::collections::HashMap;
+
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  struct Blueprint {
+    name: String,
+    health: u32,
+    kind: Kind,
+    speed: Option<f64>,
+    tags: Vec<String>,
+    parts: Vec<Part>,
+    stats: HashMap<String, i32>,
+    home: Option<(f32, f32)>,
+  }
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  enum Kind {
+    Ship,
+    Station,
+  }
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  struct Part {
+    id: char,
+    mount: Mount,
+    subparts: Vec<Part>,
+  }
+  #[allow(dead_code)]
+  #[derive(Debug, Deserialize)]
+  enum Mount {
+    Fixed(u8),
+    Turret(f32),
+  }
+
+  let doc = knurdy::example::<Blueprint>("blueprint").unwrap();
+  let text = doc.to_string();
+  assert!(text.contains("// `kind` is one of Ship, Station"));
+  assert!(text.contains("mount=(Fixed)0"));
+  assert!(text.contains("// any number of `-` children"));
+
+  // Still reads back after a round trip through text
+  let doc: KdlDocument = text.parse().unwrap();
+  let _: Blueprint = knurdy::deserialize_node(&doc.nodes()[0]).unwrap();
+}
+
+#[test]
+fn inheritance() {
+  use knurdy::inherit::{self, InheritError};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Creature {
+    health: u32,
+    speed: Option<f32>,
+    tags: Vec<String>,
+    inventory: Vec<Item>,
+    brain: Brain,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Item {
+    name: String,
+    count: u32,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Brain {
+    hostile: bool,
+    range: u32,
+  }
+
+  let doc = r#"
+    creature health=10 speed=1.0 {
+      tags "alive"
+      inventory {
+        - name="rations" count=1
+      }
+      brain hostile=#false range=5
+    }
+    armed {
+      inventory {
+        - name="dagger" count=1
+      }
+    }
+    goblin extends="creature" health=7 speed=(remove)#null {
+      inherits "armed"
+      (append)tags "goblin"
+      brain hostile=#true
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let doc = inherit::resolve(&doc).unwrap();
+  let _: KdlDocument = doc.to_string().parse().unwrap();
+  let goblin: Creature = knurdy::deserialize_node(&doc.nodes()[2]).unwrap();
+  assert_eq!(
+    goblin,
+    Creature {
+      health: 7,
+      speed: None,
+      tags: vec!["alive".into(), "goblin".into()],
+      inventory: vec![Item {
+        name: "dagger".into(),
+        count: 1
+      }],
+      brain: Brain {
+        hostile: true,
+        range: 5
+      },
+    }
+  );
+
+  let doc = r#"
+    creature health=10 {
+      tags "alive"
+      inventory {}
+      brain hostile=#false range=5
+    }
+    ghost extends="creature" {
+      (remove)tags
+      (override)brain hostile=#true range=1
+      tags "dead"
+    }
+    "#;
+  let doc: KdlDocument = doc.parse().unwrap();
+  let doc = inherit::resolve(&doc).unwrap();
+  let ghost: Creature = knurdy::deserialize_node(&doc.nodes()[1]).unwrap();
+  assert_eq!(ghost.tags, ["dead"]);
+  assert_eq!(ghost.brain.range, 1);
+
+  let doc: KdlDocument = r#"
+    a extends="b"
+    b extends="c"
+    c extends="a"
+    "#
+  .parse()
+  .unwrap();
+  assert_eq!(
+    inherit::resolve(&doc),
+    Err(InheritError::Cycle(vec![
+      "a".into(),
+      "b".into(),
+      "c".into(),
+      "a".into()
+    ]))
+  );
+  let doc: KdlDocument = "a extends=\"nobody\"".parse().unwrap();
+  assert!(matches!(
+    inherit::resolve(&doc),
+    Err(InheritError::UnknownParent { .. })
+  ));
+}
+
+#[test]
+fn includes() {
+  use std::path::Path;
+
+  use knurdy::include::{self, IncludeError, MemoryLoader};
+
+  let loader = MemoryLoader::new()
+    .with_file("pack/main.kdl", "include \"creatures/all.kdl\"\nrock 1")
+    .with_file(
+      "pack/creatures/all.kdl",
+      "include \"goblin.kdl\" \"../common/rat.kdl\"",
+    )
+    .with_file("pack/creatures/goblin.kdl", "goblin 7")
+    .with_file("pack/common/rat.kdl", "rat 2");
+  let doc = include::load("pack/main.kdl", &loader).unwrap();
+  let names: Vec<_> = doc.nodes().iter().map(|it| it.name().value()).collect();
+  assert_eq!(names, ["goblin", "rat", "rock"]);
+  let _: KdlDocument = doc.to_string().parse().unwrap();
+
+  let loader = MemoryLoader::new()
+    .with_file("a.kdl", "include \"b.kdl\"")
+    .with_file("b.kdl", "include \"./a.kdl\"");
+  let err = include::load("a.kdl", &loader).unwrap_err();
+  assert!(matches!(
+    err,
+    IncludeError::Cycle { ref file, ref chain }
+      if file == Path::new("b.kdl") && chain.len() == 3
+  ));
+
+  let loader = MemoryLoader::new()
+    .with_file("a.kdl", "include \"b.kdl\"")
+    .with_file("b.kdl", "broken {");
+  let err = include::load("a.kdl", &loader).unwrap_err();
+  assert!(err.to_string().starts_with("b.kdl: "));
+  let loader = MemoryLoader::new().with_file("a.kdl", "include \"c.kdl\"");
+  let err = include::load("a.kdl", &loader).unwrap_err();
+  assert!(err.to_string().starts_with("a.kdl: couldn't load `c.kdl`"));
+}
+
+#[test]
+fn layers() {
+  use knurdy::{
+    merge::{Layers, SeqPolicy},
+    DeError,
+  };
+  use serde::de::Error;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Config {
+    volume: u32,
+    name: String,
+    mods: Vec<String>,
+    window: Window,
+    keys: Vec<Key>,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Window {
+    width: u32,
+    height: u32,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Key {
+    action: String,
+  }
+
+  let defaults: KdlDocument = r#"
+    config volume=5 name="player" {
+      mods "base"
+      window width=800 height=600
+      keys {
+        - action="jump"
+      }
+    }
+    "#
+  .parse()
+  .unwrap();
+  let a_mod: KdlDocument = r#"
+    config {
+      mods "caves"
+      keys {
+        - action="dig"
+      }
+    }
+    "#
+  .parse()
+  .unwrap();
+  let user: KdlDocument = r#"
+    config volume=9 {
+      window width=1920
+    }
+    "#
+  .parse()
+  .unwrap();
+
+  let merged = Layers::new()
+    .with_layer("defaults", defaults.clone())
+    .with_layer("mod", a_mod.clone())
+    .with_layer("user", user.clone())
+    .merge();
+  let config: Config =
+    knurdy::deserialize_node(&merged.document().nodes()[0]).unwrap();
+  assert_eq!(config.volume, 9);
+  assert_eq!(config.mods, ["caves"]);
+  assert_eq!(
+    config.window,
+    Window {
+      width: 1920,
+      height: 600
+    }
+  );
+  assert_eq!(config.keys.len(), 1);
+  assert_eq!(merged.source("/config/volume"), Some("user"));
+  assert_eq!(merged.source("/config/name"), Some("defaults"));
+  assert_eq!(merged.source("/config/mods"), Some("mod"));
+  assert_eq!(merged.source("/config/window/width"), Some("user"));
+  assert_eq!(merged.source("/config/window/height"), Some("defaults"));
+  assert_eq!(merged.source("/config/keys/-/action"), Some("mod"));
+
+  let merged = Layers::new()
+    .with_policy(SeqPolicy::Append)
+    .with_layer("defaults", defaults.clone())
+    .with_layer("mod", a_mod)
+    .merge();
+  let config: Config =
+    knurdy::deserialize_node(&merged.document().nodes()[0]).unwrap();
+  assert_eq!(config.mods, ["base", "caves"]);
+  assert_eq!(config.keys.len(), 2);
+  assert_eq!(merged.source("/config/keys/-[0]/action"), Some("defaults"));
+  assert_eq!(merged.source("/config/keys/-[1]/action"), Some("mod"));
+
+  // Errors say which layer the bad value came from
+  let broken: KdlDocument = r#"
+    config {
+      window height="tall"
+    }
+    "#
+  .parse()
+  .unwrap();
+  let merged = Layers::new()
+    .with_layer("defaults", defaults.clone())
+    .with_layer("broken", broken)
+    .merge();
+  let node = &merged.document().nodes()[0];
+  assert_eq!(
+    merged.deserialize_node::<Config>(node),
+    Err(DeError::InLayer {
+      layer: "broken".to_owned(),
+      source: Box::new(DeError::invalid_type(
+        serde::de::Unexpected::Str("tall"),
+        &"u32"
+      )),
+    })
+  );
+
+  // Each argument keeps its own layer, even once the node has moved on
+  #[derive(Debug, Deserialize)]
+  #[allow(dead_code)]
+  struct Mods {
+    mods: Vec<String>,
+  }
+  let first: KdlDocument = "config { mods 5; }".parse().unwrap();
+  let second: KdlDocument =
+    r#"config { (append)mods "caves"; }"#.parse().unwrap();
+  let merged = Layers::new()
+    .with_layer("first", first)
+    .with_layer("second", second)
+    .merge();
+  assert_eq!(merged.source("/config/mods"), Some("second"));
+  let err = merged
+    .deserialize_node::<Mods>(&merged.document().nodes()[0])
+    .unwrap_err();
+  assert!(matches!(err, DeError::InLayer { layer, .. } if layer == "first"));
+
+  // Bools go through a different path than other scalars, and a clone
+  // keeps track of layers too
+  #[derive(Debug, Deserialize)]
+  #[allow(dead_code)]
+  struct Display {
+    fullscreen: bool,
+  }
+  let base: KdlDocument = "display { fullscreen #false; }".parse().unwrap();
+  let user: KdlDocument = r#"display { fullscreen "yes"; }"#.parse().unwrap();
+  let merged = Layers::new()
+    .with_layer("base", base)
+    .with_layer("user", user)
+    .merge()
+    .clone();
+  let err = merged
+    .deserialize_node::<Display>(&merged.document().nodes()[0])
+    .unwrap_err();
+  assert!(matches!(err, DeError::InLayer { layer, .. } if layer == "user"));
+}
+
+#[test]
+fn interpolation() {
+  use knurdy::DeOptions;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Sprite {
+    path: String,
+    tint: String,
+    frames: Vec<String>,
+  }
+
+  let doc: KdlDocument = r#"
+    vars {
+      root "assets"
+      icons "${root}/icons"
+    }
+    sprite path="${icons}/goblin.png" tint="$${not-a-var} ${tint}" {
+      frames "${user}/a.png" "$$5"
+    }
+    "#
+  .parse()
+  .unwrap();
+  let options = DeOptions::new()
+    .with_interpolation(true)
+    .with_var("tint", "green")
+    .with_var("user", "mods")
+    .with_document_vars(&doc)
+    .unwrap();
+  let sprite: Sprite =
+    knurdy::deserialize_node_with(&doc.nodes()[1], &options).unwrap();
+  assert_eq!(
+    sprite,
+    Sprite {
+      path: "assets/icons/goblin.png".into(),
+      tint: "${not-a-var} green".into(),
+      frames: vec!["mods/a.png".into(), "$5".into()],
+    }
+  );
+
+  // Off unless asked for
+  let sprite: Sprite = knurdy::deserialize_node(&doc.nodes()[1]).unwrap();
+  assert_eq!(sprite.path, "${icons}/goblin.png");
+
+  let options = DeOptions::new().with_interpolation(true);
+  let err =
+    knurdy::deserialize_node_with::<Sprite>(&doc.nodes()[1], &options)
+      .unwrap_err();
+  assert_eq!(err.to_string(), "`${icons}` isn't defined");
+
+  std::env::set_var("KNURDY_TEST_ROOT", "/srv");
+  let options = DeOptions::new()
+    .with_interpolation(true)
+    .with_env_vars(true);
+  let node: KdlDocument = r#"- "${KNURDY_TEST_ROOT}/x""#.parse().unwrap();
+  let path: String =
+    knurdy::deserialize_node_with(&node.nodes()[0], &options).unwrap();
+  assert_eq!(path, "/srv/x");
+}
+
+#[test]
+fn anchors() {
+  use knurdy::anchor::{self, AnchorError};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Creature {
+    health: u32,
+    loot: Vec<Drop>,
+    stats: Stats,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Drop {
+    item: String,
+  }
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Stats {
+    strength: u32,
+    agility: u32,
+  }
+
+  let doc: KdlDocument = r#"
+    tables {
+      - id="common-loot" {
+        - item="coin"
+        - item="rag"
+      }
+      - id="weak" strength=1 agility=(ref)"never-mind"
+    }
+    goblin health=5 stats=(ref)"average" {
+      loot ref="common-loot"
+    }
+    rat health=1 loot=(ref)"common-loot" {
+      stats (ref)"average" agility=9
+    }
+    stats id="average" strength=3 agility=3
+    "#
+  .parse()
+  .unwrap();
+  // Even anchors nothing refers to can't point nowhere
+  assert_eq!(
+    anchor::resolve(&doc),
+    Err(AnchorError::Dangling("never-mind".into()))
+  );
+
+  let doc: KdlDocument = doc
+    .to_string()
+    .replace(r#"agility=(ref)"never-mind""#, "agility=1")
+    .parse()
+    .unwrap();
+  // Deserializing doesn't follow references on its own
+  assert!(knurdy::deserialize_node::<Creature>(&doc.nodes()[1]).is_err());
+  let doc = anchor::resolve(&doc).unwrap();
+  let goblin: Creature = knurdy::deserialize_node(&doc.nodes()[1]).unwrap();
+  assert_eq!(goblin.loot.len(), 2);
+  assert_eq!(
+    goblin.stats,
+    Stats {
+      strength: 3,
+      agility: 3
+    }
+  );
+  let rat: Creature = knurdy::deserialize_node(&doc.nodes()[2]).unwrap();
+  assert_eq!(rat.loot[1].item, "rag");
+  assert_eq!(rat.stats.agility, 9);
+
+  let doc: KdlDocument = r#"
+    a id="a" { b ref="b" }
+    b id="b" { a ref="a" }
+    "#
+  .parse()
+  .unwrap();
+  assert!(matches!(anchor::resolve(&doc), Err(AnchorError::Cycle(_))));
+}
+
+#[test]
+fn queries() {
+  use knurdy::{Query, QueryError};
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Spawner {
+    creature: String,
+    rate: f32,
+  }
+
+  let doc: KdlDocument = r#"
+    zone biome="desert" {
+      spawner creature="scorpion" rate=0.5
+      ruins {
+        spawner creature="mummy" rate=0.1
+      }
+    }
+    zone biome="jungle" {
+      spawner creature="snapjaw" rate=0.8
+    }
+    (boss)zone biome="desert" level=10 {
+      spawner creature="sphinx" rate=1.0
+    }
+    "#
+  .parse()
+  .unwrap();
+
+  let spawners: Vec<Spawner> =
+    knurdy::query(&doc, r#"zone[biome="desert"] >> spawner"#).unwrap();
+  let names: Vec<_> = spawners.iter().map(|it| &*it.creature).collect();
+  assert_eq!(names, ["scorpion", "mummy", "sphinx"]);
+
+  let select = |query: &str| {
+    let nodes = Query::parse(query).unwrap().select(&doc);
+    nodes
+      .iter()
+      .map(|it| match it.get("creature") {
+        Some(creature) => creature.as_string().unwrap().to_owned(),
+        None => it.name().value().to_owned(),
+      })
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(select("zone > spawner[rate >= 0.8]"), ["snapjaw", "sphinx"]);
+  assert_eq!(select("(boss)zone > spawner"), ["sphinx"]);
+  assert_eq!(select("top() > [level]"), ["zone"]);
+  assert_eq!(select("spawner + ruins"), ["ruins"]);
+  assert_eq!(select("zone[biome ^= \"jun\"] ++ zone"), ["zone"]);
+  assert_eq!(
+    select("ruins > spawner || [creature=snapjaw]"),
+    ["mummy", "snapjaw"]
+  );
+  assert_eq!(select("[name() *= \"uin\"]"), ["ruins"]);
+
+  assert!(matches!(
+    Query::parse("zone >"),
+    Err(QueryError::Syntax { offset: 6, .. })
+  ));
+
+  // Too big to tell apart as floats
+  let ids: KdlDocument =
+    "item id=9007199254740992; item id=1.5".parse().unwrap();
+  let count = |query: &str| Query::parse(query).unwrap().select(&ids).len();
+  assert_eq!(count("[id=9007199254740993]"), 0);
+  assert_eq!(count("[id=9007199254740992]"), 1);
+  assert_eq!(count("[id<9007199254740993]"), 2);
+  assert_eq!(count("[id>1]"), 2);
+
+  let options = knurdy::DeOptions::new()
+    .with_interpolation(true)
+    .with_var("pet", "camel");
+  let doc: KdlDocument =
+    r#"spawner creature="${pet}" rate=0.2"#.parse().unwrap();
+  let spawners: Vec<Spawner> =
+    knurdy::query_with(&doc, "spawner", &options).unwrap();
+  assert_eq!(spawners[0].creature, "camel");
+}